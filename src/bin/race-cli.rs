@@ -0,0 +1,137 @@
+use std::{fs, process::ExitCode};
+
+use anyhow::{Context, anyhow, bail};
+use race::{
+    course::Course,
+    level::LevelData,
+    records::run_race,
+    render::{SoftwareGraphics, Sprites, race_to_webp},
+    save::courses_from_toml,
+    share::decode,
+    ui::loader::load_levels,
+};
+
+static USAGE: &str = "\
+Usage: race-cli render [OPTIONS] OUTPUT
+
+Renders a course without opening a window.  If OUTPUT ends in .webp, the
+whole race is saved as an animation; otherwise a PNG of a single round is
+saved.
+
+Options:
+  --level N             Use the built-in solution to level N
+  --share CODE          Use the level and course in a share code
+  --round N             The round to show in a PNG [default: 0]
+  --tile-size PIXELS    The width of a tile [default: 48]
+  --frames-per-round N  Animation frames per round [default: 8]
+  --delay MS            Milliseconds per animation frame [default: 60]
+";
+
+struct RenderArgs {
+    source: Option<(LevelData, Course)>,
+    output: Option<String>,
+    round: usize,
+    tile_size: u32,
+    frames_per_round: usize,
+    delay: u16,
+}
+
+fn builtin_solution(n: &str) -> Result<(LevelData, Course), anyhow::Error> {
+    let idx: usize = n.parse().context("level must be a number")?;
+    let levels = load_levels();
+    let lvl = levels
+        .get(idx)
+        .ok_or_else(|| anyhow!("there are only {} levels", levels.len()))?;
+    let mut solutions = courses_from_toml(include_str!("../../res/solutions.toml"))?;
+    let course = solutions
+        .remove(&format!("Level {idx}"))
+        .ok_or_else(|| anyhow!("level {idx} has no built-in solution"))?;
+    Ok((lvl.as_ref().clone(), course))
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<RenderArgs, anyhow::Error> {
+    let mut parsed = RenderArgs {
+        source: None,
+        output: None,
+        round: 0,
+        tile_size: 48,
+        frames_per_round: 8,
+        delay: 60,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| anyhow!("{arg} needs a value"));
+        match arg.as_str() {
+            "--level" => parsed.source = Some(builtin_solution(&value()?)?),
+            "--share" => {
+                let shared = decode(&value()?)?;
+                parsed.source = Some((shared.level, shared.course));
+            }
+            "--round" => parsed.round = value()?.parse()?,
+            "--tile-size" => parsed.tile_size = value()?.parse()?,
+            "--frames-per-round" => parsed.frames_per_round = value()?.parse()?,
+            "--delay" => parsed.delay = value()?.parse()?,
+            _ if arg.starts_with("--") => bail!("unknown option {arg}"),
+            _ if parsed.output.is_none() => parsed.output = Some(arg.clone()),
+            _ => bail!("unexpected argument {arg}"),
+        }
+    }
+    if parsed.tile_size < 2 {
+        bail!("the tile size must be at least 2");
+    }
+    if parsed.frames_per_round == 0 {
+        bail!("there must be at least one frame per round");
+    }
+    Ok(parsed)
+}
+
+fn render(args: impl Iterator<Item = String>) -> Result<(), anyhow::Error> {
+    let args = parse_args(args)?;
+    let (lvl, course) = args
+        .source
+        .ok_or_else(|| anyhow!("either --level or --share is required"))?;
+    let output = args.output.ok_or_else(|| anyhow!("no output file given"))?;
+    if course.is_empty() {
+        bail!("the course is empty");
+    }
+    let sprites = Sprites::load();
+    let mut graphics = SoftwareGraphics::for_course(&sprites, &course, args.tile_size, &[1.0; 3]);
+    let tracker = run_race(&course, lvl.cars);
+    let bytes = if output.ends_with(".webp") {
+        race_to_webp(
+            &mut graphics,
+            &course,
+            &tracker,
+            args.frames_per_round,
+            args.delay,
+        )?
+    } else {
+        let cars = tracker
+            .get_cars()
+            .get(args.round)
+            .ok_or_else(|| anyhow!("the race has {} rounds", tracker.rounds_available()))?;
+        graphics.round = args.round;
+        graphics.draw_course(&course);
+        graphics.draw_cars(cars, &[], 0.0);
+        graphics.draw.image.to_png()?
+    };
+    fs::write(&output, bytes).with_context(|| format!("failed to write {output}"))?;
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let result = match args.next().as_deref() {
+        Some("render") => render(args),
+        _ => {
+            eprint!("{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("race-cli: {e:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
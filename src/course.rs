@@ -3,12 +3,14 @@ use serde::{Deserialize, Serialize};
 use takeable::Takeable;
 
 use crate::direction::Direction;
+use crate::history::UndoTree;
 use crate::level::LevelData;
-use crate::save::save_course;
+use crate::save::{load_course_slot, load_history, load_slots, save_course_slot, save_history};
 use crate::tile::{Tile, TileType};
 use std::borrow::Borrow;
 use std::ops::{Add, Neg, RangeInclusive, Sub};
 use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 pub type Course = rpds::HashTrieMap<
     TileCoord,
@@ -88,7 +90,7 @@ where
     (xmin..=xmax, ymin..=ymax)
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct CourseEditState {
     course: Course,
     finish: Option<TileCoord>,
@@ -133,12 +135,28 @@ impl CourseEditState {
         });
         Self { finish, course }
     }
+    pub fn course(&self) -> &Course {
+        &self.course
+    }
+}
+
+/// Number of edits between writes of the undo history to storage.
+static HISTORY_SAVE_INTERVAL: usize = 20;
+
+/// A number that is different for every version of every course being
+/// edited, so that a course doesn't have to be compared to tell whether it
+/// has changed.
+fn next_revision() -> usize {
+    static REVISION: AtomicUsize = AtomicUsize::new(0);
+    REVISION.fetch_add(1, Ordering::Relaxed)
 }
 
 pub struct CourseEdit {
-    stack: Vec<CourseEditState>,
-    pos: usize,
+    history: UndoTree,
     level: Rc<LevelData>,
+    slot: usize,
+    unsaved_history: usize,
+    revision: usize,
 }
 
 pub struct Transaction<'a> {
@@ -192,19 +210,37 @@ impl CourseEdit {
     pub fn new(course: Course, level: Rc<LevelData>) -> Self {
         let state = CourseEditState::from_course(course);
         Self {
-            stack: vec![state],
-            pos: 0,
+            history: UndoTree::new(state),
+            slot: load_slots(&level).active_slot().id,
             level,
+            unsaved_history: 0,
+            revision: next_revision(),
+        }
+    }
+    /// Loads the course in the active save slot, together with its undo
+    /// history if the history is still in sync with the course.
+    pub fn load(level: Rc<LevelData>) -> Self {
+        let slot = load_slots(&level).active_slot().id;
+        let course = load_course_slot(&level, slot).unwrap_or_default();
+        let history = load_history(&level, slot)
+            .filter(|h| h.current().course() == &course)
+            .unwrap_or_else(|| UndoTree::new(CourseEditState::from_course(course)));
+        Self {
+            history,
+            level,
+            slot,
+            unsaved_history: 0,
+            revision: next_revision(),
         }
     }
     fn get_state(&self) -> &CourseEditState {
-        &self.stack[self.pos]
+        self.history.current()
     }
     pub fn get_course(&self) -> &Course {
         &self.get_state().course
     }
-    pub fn take_course(mut self) -> Course {
-        self.stack.pop().unwrap().course
+    pub fn take_course(self) -> Course {
+        self.get_course().clone()
     }
     pub fn get_finish(&self) -> Option<TileCoord> {
         self.get_state().finish
@@ -221,27 +257,61 @@ impl CourseEdit {
     pub fn set_course(&mut self, course: Course) {
         self.push(CourseEditState::from_course(course));
     }
-    pub fn save(&self) {
-        save_course(&self.level, self.get_course());
+    /// The id of the save slot that the course is saved in.
+    pub fn slot(&self) -> usize {
+        self.slot
+    }
+    pub fn history(&self) -> &UndoTree {
+        &self.history
+    }
+    /// Changes whenever the course changes.
+    pub fn revision(&self) -> usize {
+        self.revision
+    }
+    pub fn save(&mut self) {
+        self.revision = next_revision();
+        save_course_slot(&self.level, self.slot, self.get_course());
+        self.unsaved_history += 1;
+        if self.unsaved_history >= HISTORY_SAVE_INTERVAL {
+            self.save_history();
+        }
+    }
+    pub fn save_history(&mut self) {
+        save_history(&self.level, self.slot, &self.history);
+        self.unsaved_history = 0;
+    }
+    /// Saves the undo history if it has changed since it was last saved.
+    /// Call this before leaving the editor, since the history is only saved
+    /// every few edits.
+    pub fn flush_history(&mut self) {
+        if self.unsaved_history > 0 {
+            self.save_history();
+        }
     }
     fn push(&mut self, st: CourseEditState) {
-        self.stack.truncate(self.pos + 1);
-        self.stack.push(st);
-        self.pos += 1;
+        self.history.push(st);
         self.save();
     }
     pub fn undo(&mut self) {
-        if self.pos > 0 {
-            self.pos -= 1;
+        if self.history.undo() {
             self.save();
         }
     }
     pub fn redo(&mut self) {
-        if self.pos < self.stack.len() - 1 {
-            self.pos += 1;
+        if self.history.redo() {
             self.save();
         }
     }
+    pub fn jump(&mut self, n: usize) {
+        if self.history.jump(n) {
+            self.save();
+            self.save_history();
+        }
+    }
+    pub fn set_checkpoint(&mut self, name: Option<String>) {
+        self.history.set_checkpoint(name);
+        self.save_history();
+    }
 }
 
 pub fn course_center(course: &Course) -> Vec2 {
@@ -0,0 +1,378 @@
+use std::{fmt::Display, iter::Peekable, str::Chars};
+
+use crate::level::MAX_CARS;
+
+/// An error in a finish order expression, with the 1-based position where it
+/// was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FinishError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl Display for FinishError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.line > 1 {
+            write!(
+                f,
+                "line {}, column {}: {}",
+                self.line, self.column, self.message
+            )
+        } else {
+            write!(f, "column {}: {}", self.column, self.message)
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Pos {
+    line: usize,
+    column: usize,
+}
+
+impl Pos {
+    fn error(self, message: impl Into<String>) -> FinishError {
+        FinishError {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+enum Token {
+    Num(usize),
+    Ident(String),
+    Range,
+    RangeInclusive,
+    Open(char),
+    Close(char),
+    Comma,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Self::Num(n) => format!("number {n}"),
+            Self::Ident(s) => format!("\"{s}\""),
+            Self::Range => "\"..\"".to_string(),
+            Self::RangeInclusive => "\"..=\"".to_string(),
+            Self::Open(c) | Self::Close(c) => format!("\"{c}\""),
+            Self::Comma => "\",\"".to_string(),
+        }
+    }
+}
+
+struct Lexer<'a> {
+    chars: Peekable<Chars<'a>>,
+    pos: Pos,
+}
+
+impl Lexer<'_> {
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn bump(&mut self) {
+        if self.chars.next() == Some('\n') {
+            self.pos.line += 1;
+            self.pos.column = 1;
+        } else {
+            self.pos.column += 1;
+        }
+    }
+
+    fn token(&mut self, c: char) -> Result<Token, FinishError> {
+        let start = self.pos;
+        if c.is_ascii_digit() {
+            let mut n: usize = 0;
+            while let Some(d) = self.peek().and_then(|c| c.to_digit(10)) {
+                self.bump();
+                n = n
+                    .checked_mul(10)
+                    .and_then(|n| n.checked_add(d as usize))
+                    .ok_or_else(|| start.error("number is too large"))?;
+            }
+            return Ok(Token::Num(n));
+        }
+        if c.is_alphabetic() || c == '_' {
+            let mut s = String::new();
+            while let Some(c) = self.peek().filter(|&c| c.is_alphanumeric() || c == '_') {
+                self.bump();
+                s.push(c);
+            }
+            return Ok(Token::Ident(s));
+        }
+        self.bump();
+        match c {
+            '.' if self.peek() == Some('.') => {
+                self.bump();
+                if self.peek() == Some('=') {
+                    self.bump();
+                    Ok(Token::RangeInclusive)
+                } else {
+                    Ok(Token::Range)
+                }
+            }
+            '(' | '[' => Ok(Token::Open(c)),
+            ')' | ']' => Ok(Token::Close(c)),
+            ',' | ';' => Ok(Token::Comma),
+            _ => Err(start.error(format!("unexpected character \"{c}\""))),
+        }
+    }
+}
+
+/// Splits the text into tokens.  Also returns the position just past the end
+/// of the text.
+fn tokenize(text: &str) -> Result<(Vec<(Token, Pos)>, Pos), FinishError> {
+    let mut lexer = Lexer {
+        chars: text.chars().peekable(),
+        pos: Pos { line: 1, column: 1 },
+    };
+    let mut tokens = Vec::new();
+    while let Some(c) = lexer.peek() {
+        if c.is_whitespace() {
+            lexer.bump();
+        } else {
+            let pos = lexer.pos;
+            tokens.push((lexer.token(c)?, pos));
+        }
+    }
+    Ok((tokens, lexer.pos))
+}
+
+/// Car numbers, each with the position of the expression that produced it.
+type Values = Vec<(usize, Pos)>;
+
+struct Parser {
+    tokens: Vec<(Token, Pos)>,
+    idx: usize,
+    end: Pos,
+}
+
+fn check_len(len: usize, pos: Pos) -> Result<(), FinishError> {
+    if len > MAX_CARS {
+        Err(pos.error(format!("the finish order can have at most {MAX_CARS} cars")))
+    } else {
+        Ok(())
+    }
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.idx).map(|(t, _)| t)
+    }
+
+    fn pos(&self) -> Pos {
+        self.tokens.get(self.idx).map_or(self.end, |(_, p)| *p)
+    }
+
+    fn next(&mut self) -> Option<(Token, Pos)> {
+        let t = self.tokens.get(self.idx).cloned();
+        self.idx += 1;
+        t
+    }
+
+    fn unexpected(&self) -> FinishError {
+        match self.peek() {
+            Some(t) => self.pos().error(format!("unexpected {}", t.describe())),
+            None => self.end.error("unexpected end of input"),
+        }
+    }
+
+    fn expect_num(&mut self) -> Result<usize, FinishError> {
+        match self.peek() {
+            Some(&Token::Num(n)) => {
+                self.idx += 1;
+                Ok(n)
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    /// Parses items separated by whitespace, and also by commas unless
+    /// `in_args` is set, in which case commas separate function arguments.
+    fn list(&mut self, in_args: bool) -> Result<Values, FinishError> {
+        let mut values = Vec::new();
+        loop {
+            match self.peek() {
+                None | Some(Token::Close(_)) => break,
+                Some(Token::Comma) if in_args => break,
+                Some(Token::Comma) => self.idx += 1,
+                _ => {
+                    let pos = self.pos();
+                    values.extend(self.item()?);
+                    check_len(values.len(), pos)?;
+                }
+            }
+        }
+        Ok(values)
+    }
+
+    fn item(&mut self) -> Result<Values, FinishError> {
+        let (token, pos) = self
+            .next()
+            .ok_or_else(|| self.end.error("unexpected end of input"))?;
+        match token {
+            Token::Num(start) => match self.peek() {
+                Some(Token::Range) | Some(Token::RangeInclusive) => {
+                    let inclusive = self.peek() == Some(&Token::RangeInclusive);
+                    self.idx += 1;
+                    let end = self.expect_num()?;
+                    let end = if inclusive {
+                        end.saturating_add(1)
+                    } else {
+                        end
+                    };
+                    let step = if matches!(self.peek(), Some(Token::Ident(s)) if s == "step") {
+                        self.idx += 1;
+                        let step_pos = self.pos();
+                        match self.expect_num()? {
+                            0 => return Err(step_pos.error("step must be at least 1")),
+                            s => s,
+                        }
+                    } else {
+                        1
+                    };
+                    check_len(end.saturating_sub(start).div_ceil(step), pos)?;
+                    Ok((start..end).step_by(step).map(|n| (n, pos)).collect())
+                }
+                _ => Ok(vec![(start, pos)]),
+            },
+            Token::Ident(name) => {
+                match self.next() {
+                    Some((Token::Open('('), _)) => (),
+                    _ => return Err(pos.error(format!("expected \"(\" after \"{name}\""))),
+                }
+                let args = self.args()?;
+                call(&name, args, pos)
+            }
+            Token::Open(open) => {
+                let values = self.list(false)?;
+                let close = if open == '(' { ')' } else { ']' };
+                match self.peek() {
+                    Some(&Token::Close(c)) if c == close => {
+                        self.idx += 1;
+                        Ok(values)
+                    }
+                    _ => Err(self.unexpected()),
+                }
+            }
+            t => Err(pos.error(format!("unexpected {}", t.describe()))),
+        }
+    }
+
+    fn args(&mut self) -> Result<Vec<(Values, Pos)>, FinishError> {
+        let mut args = Vec::new();
+        loop {
+            let pos = self.pos();
+            args.push((self.list(true)?, pos));
+            match self.next() {
+                Some((Token::Comma, _)) => (),
+                Some((Token::Close(')'), _)) => return Ok(args),
+                _ => {
+                    self.idx -= 1;
+                    return Err(self.unexpected());
+                }
+            }
+        }
+    }
+}
+
+fn number_arg(arg: &(Values, Pos)) -> Result<usize, FinishError> {
+    match arg.0[..] {
+        [(n, _)] => Ok(n),
+        _ => Err(arg.1.error("expected a single number")),
+    }
+}
+
+fn expect_args(
+    name: &str,
+    args: &[(Values, Pos)],
+    range: &[usize],
+    pos: Pos,
+) -> Result<(), FinishError> {
+    if range.contains(&args.len()) {
+        Ok(())
+    } else {
+        let expected: Vec<String> = range.iter().map(|n| n.to_string()).collect();
+        Err(pos.error(format!(
+            "\"{name}\" takes {} arguments",
+            expected.join(" or ")
+        )))
+    }
+}
+
+fn call(name: &str, args: Vec<(Values, Pos)>, pos: Pos) -> Result<Values, FinishError> {
+    match name {
+        "rev" => Ok(args.into_iter().flat_map(|(v, _)| v).rev().collect()),
+        "evens" | "odds" => {
+            expect_args(name, &args, &[1], pos)?;
+            let n = number_arg(&args[0])?;
+            check_len(n.div_ceil(2), pos)?;
+            let start = if name == "evens" { 0 } else { 1 };
+            Ok((start..n).step_by(2).map(|n| (n, pos)).collect())
+        }
+        "interleave" => {
+            let longest = args.iter().map(|(v, _)| v.len()).max().unwrap_or(0);
+            Ok((0..longest)
+                .flat_map(|i| args.iter().filter_map(move |(v, _)| v.get(i).copied()))
+                .collect())
+        }
+        "repeat" => {
+            expect_args(name, &args, &[2, 3], pos)?;
+            let values = &args[0].0;
+            let count = number_arg(&args[1])?;
+            let stride = match args.get(2) {
+                Some(arg) => number_arg(arg)?,
+                None => values.iter().map(|&(n, _)| n + 1).max().unwrap_or(0),
+            };
+            check_len(values.len().saturating_mul(count), pos)?;
+            let mut result = Vec::with_capacity(values.len() * count);
+            for k in 0..count {
+                for &(n, p) in values {
+                    let shifted = k
+                        .checked_mul(stride)
+                        .and_then(|s| s.checked_add(n))
+                        .ok_or_else(|| pos.error("number is too large"))?;
+                    result.push((shifted, p));
+                }
+            }
+            Ok(result)
+        }
+        _ => Err(pos.error(format!("unknown function \"{name}\""))),
+    }
+}
+
+/// Evaluates a finish order expression.  Besides plain lists of car numbers,
+/// this supports ranges (`0..10`, `0..=9`, `0..10 step 2`) and the functions
+/// `rev(...)`, `evens(n)`, `odds(n)`, `interleave(a, b, ...)` and
+/// `repeat(list, count[, stride])`.
+pub fn parse_finish(text: &str, cars: usize) -> Result<Vec<usize>, FinishError> {
+    let (tokens, end) = tokenize(text)?;
+    let mut parser = Parser {
+        tokens,
+        idx: 0,
+        end,
+    };
+    let values = parser.list(false)?;
+    if parser.peek().is_some() {
+        return Err(parser.unexpected());
+    }
+    let mut seen = vec![false; cars];
+    values
+        .into_iter()
+        .map(|(n, pos)| match seen.get_mut(n) {
+            None => Err(pos.error(format!(
+                "car {n} does not exist, because the level has {cars} cars"
+            ))),
+            Some(&mut true) => Err(pos.error(format!("car {n} finishes more than once"))),
+            Some(s) => {
+                *s = true;
+                Ok(n)
+            }
+        })
+        .collect()
+}
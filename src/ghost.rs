@@ -0,0 +1,140 @@
+//! A race run in the editor on the course as it is being edited, so that
+//! its cars can be drawn over the course.
+
+use std::time::Duration;
+
+use crate::{
+    course::Course,
+    level::LevelData,
+    playback::{CarAnimation, Playback, animations},
+    simulator::{CarData, Simulator},
+    tracker::Tracker,
+    traffic::{Divergence, first_divergence},
+};
+
+/// The number of rounds simulated per frame, so that a long race doesn't
+/// hold up editing.
+const ROUNDS_PER_FRAME: usize = 50;
+
+/// Whether the race has ended, either by itself or at the round limit.
+fn race_over(sim: &Simulator, tracker: &Tracker, max_rounds: Option<usize>) -> bool {
+    sim.is_finished()
+        || tracker.is_loop_detected()
+        || max_rounds.is_some_and(|m| tracker.rounds_available() > m)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum GhostView {
+    /// Plays the race over and over.
+    Loop,
+    /// Shows the cars in one round.
+    Round(usize),
+}
+
+pub struct GhostRace {
+    pub view: GhostView,
+    /// The revision of the course that the race is run on.
+    revision: Option<usize>,
+    /// `None` if the course has no finish to spawn cars from.
+    sim: Option<Simulator>,
+    max_rounds: Option<usize>,
+    pub tracker: Tracker,
+    pub divergence: Option<Divergence>,
+    pub round: usize,
+    pub animations: Vec<CarAnimation>,
+    pub round_display_time: Duration,
+}
+
+impl GhostRace {
+    pub fn new(cars: usize) -> Self {
+        Self {
+            view: GhostView::Loop,
+            revision: None,
+            sim: None,
+            max_rounds: None,
+            tracker: Tracker::new(cars),
+            divergence: None,
+            round: 0,
+            animations: Vec::new(),
+            round_display_time: Default::default(),
+        }
+    }
+
+    /// Whether there is a race to show, which needs a finish on the course.
+    pub fn has_race(&self) -> bool {
+        self.sim.is_some()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.sim
+            .as_ref()
+            .is_none_or(|sim| race_over(sim, &self.tracker, self.max_rounds))
+    }
+
+    /// Starts a new race if the course has a different revision from the
+    /// last one and simulates some more rounds of the current race, stopping
+    /// at the round limit of the level.
+    pub fn update(
+        &mut self,
+        course: &Course,
+        revision: usize,
+        has_finish: bool,
+        level: &LevelData,
+    ) {
+        let cars = level.cars;
+        if self.revision != Some(revision) {
+            self.revision = Some(revision);
+            self.sim = has_finish.then(|| Simulator::new(course.clone(), cars));
+            self.max_rounds = level.max_rounds;
+            self.tracker = Tracker::new(cars);
+            self.divergence = None;
+            self.round = 0;
+            self.animations.clear();
+        }
+        let Some(sim) = &mut self.sim else {
+            return;
+        };
+        for _ in 0..ROUNDS_PER_FRAME {
+            if race_over(sim, &self.tracker, self.max_rounds) {
+                break;
+            }
+            sim.run_round();
+            for ev in sim.events() {
+                self.tracker.process_event(ev);
+            }
+            if race_over(sim, &self.tracker, self.max_rounds) {
+                self.tracker.compute_final_crashes(cars);
+                self.divergence = first_divergence(&self.tracker, &level.finish);
+            }
+        }
+    }
+
+    /// Moves on to the round that should be shown at `time`.
+    pub fn advance(&mut self, time: Duration) {
+        let last = self.tracker.rounds_available() - 1;
+        match self.view {
+            GhostView::Round(r) => {
+                self.round = r.min(last);
+                self.animations.clear();
+            }
+            GhostView::Loop => {
+                if time - self.round_display_time < Playback::Playing.frame_duration() {
+                    return;
+                }
+                self.round_display_time = time;
+                if self.round < last {
+                    self.round += 1;
+                    let cars = self.tracker.get_cars();
+                    self.animations = animations(&cars[self.round - 1], &cars[self.round]);
+                } else if self.is_finished() {
+                    self.round = 0;
+                    self.animations.clear();
+                }
+            }
+        }
+    }
+
+    pub fn get_cars(&self) -> &[CarData] {
+        &self.tracker.get_cars()[self.round]
+    }
+}
@@ -0,0 +1,204 @@
+use serde::{Deserialize, Serialize};
+
+use crate::course::CourseEditState;
+
+pub static MAX_HISTORY: usize = 200;
+/// The total number of tiles kept over all nodes, since each node stores a
+/// whole course.
+pub static MAX_HISTORY_TILES: usize = 50_000;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HistoryNode {
+    pub state: CourseEditState,
+    pub parent: Option<usize>,
+    redo: Option<usize>,
+    pub checkpoint: Option<String>,
+}
+
+/// Undo history that keeps every branch.  Undoing and then making a new edit
+/// starts a new branch instead of discarding the old one.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct UndoTree {
+    nodes: Vec<HistoryNode>,
+    pos: usize,
+}
+
+impl UndoTree {
+    pub fn new(state: CourseEditState) -> Self {
+        Self {
+            nodes: vec![HistoryNode {
+                state,
+                parent: None,
+                redo: None,
+                checkpoint: None,
+            }],
+            pos: 0,
+        }
+    }
+
+    pub fn current(&self) -> &CourseEditState {
+        &self.nodes[self.pos].state
+    }
+
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    pub fn nodes(&self) -> &[HistoryNode] {
+        &self.nodes
+    }
+
+    pub fn push(&mut self, state: CourseEditState) {
+        self.nodes.push(HistoryNode {
+            state,
+            parent: Some(self.pos),
+            redo: None,
+            checkpoint: None,
+        });
+        let new_pos = self.nodes.len() - 1;
+        self.nodes[self.pos].redo = Some(new_pos);
+        self.pos = new_pos;
+        self.prune();
+    }
+
+    pub fn undo(&mut self) -> bool {
+        match self.nodes[self.pos].parent {
+            Some(parent) => {
+                self.nodes[parent].redo = Some(self.pos);
+                self.pos = parent;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn redo(&mut self) -> bool {
+        match self.nodes[self.pos].redo {
+            Some(child) => {
+                self.pos = child;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn jump(&mut self, n: usize) -> bool {
+        if n == self.pos || n >= self.nodes.len() {
+            return false;
+        }
+        let mut child = n;
+        while let Some(parent) = self.nodes[child].parent {
+            self.nodes[parent].redo = Some(child);
+            child = parent;
+        }
+        self.pos = n;
+        true
+    }
+
+    pub fn set_checkpoint(&mut self, name: Option<String>) {
+        self.nodes[self.pos].checkpoint = name;
+    }
+
+    /// Lists the nodes in depth-first order, together with the number of
+    /// branches that separate each node from the root.
+    pub fn display_order(&self) -> Vec<(usize, usize)> {
+        let mut children = vec![Vec::new(); self.nodes.len()];
+        let mut roots = Vec::new();
+        for (n, node) in self.nodes.iter().enumerate() {
+            match node.parent {
+                Some(p) => children[p].push(n),
+                None => roots.push(n),
+            }
+        }
+        let mut order = Vec::with_capacity(self.nodes.len());
+        let mut stack: Vec<(usize, usize)> = roots.into_iter().rev().map(|n| (n, 0)).collect();
+        while let Some((n, depth)) = stack.pop() {
+            order.push((n, depth));
+            for (i, &c) in children[n].iter().enumerate().rev() {
+                stack.push((c, if i == 0 { depth } else { depth + 1 }));
+            }
+        }
+        order
+    }
+
+    fn num_tiles(&self) -> usize {
+        self.nodes.iter().map(|n| n.state.course().size()).sum()
+    }
+
+    /// Drops nodes until the history is within `MAX_HISTORY` nodes and
+    /// `MAX_HISTORY_TILES` tiles.  Branches off the current undo path go
+    /// first, oldest first and always as a whole, so that no branch loses a
+    /// step in the middle; after that the oldest steps of the path are
+    /// dropped.
+    fn prune(&mut self) {
+        while self.nodes.len() > MAX_HISTORY || self.num_tiles() > MAX_HISTORY_TILES {
+            let mut path = Vec::new();
+            let mut n = Some(self.pos);
+            while let Some(i) = n {
+                path.push(i);
+                n = self.nodes[i].parent;
+            }
+            let mut on_path = vec![false; self.nodes.len()];
+            for &i in &path {
+                on_path[i] = true;
+            }
+            let mut num_children = vec![0; self.nodes.len()];
+            for node in &self.nodes {
+                if let Some(p) = node.parent {
+                    num_children[p] += 1;
+                }
+            }
+            let removable = |i: usize| {
+                !on_path[i] && num_children[i] <= 1 && self.nodes[i].checkpoint.is_none()
+            };
+            let leaf = (0..self.nodes.len()).find(|&i| num_children[i] == 0 && removable(i));
+            if let Some(leaf) = leaf {
+                let mut branch = vec![leaf];
+                while let Some(p) = self.nodes[branch[branch.len() - 1]].parent
+                    && removable(p)
+                {
+                    branch.push(p);
+                }
+                branch.sort_unstable_by(|a, b| b.cmp(a));
+                for i in branch {
+                    self.remove_node(i);
+                }
+                continue;
+            }
+            let root = path[path.len() - 1];
+            if root == self.pos {
+                break;
+            }
+            if self.nodes[root].checkpoint.is_some() {
+                // Keep the checkpoint as a separate tree that can still be
+                // jumped to.
+                let child = path[path.len() - 2];
+                self.nodes[child].parent = None;
+                if self.nodes[root].redo == Some(child) {
+                    self.nodes[root].redo = None;
+                }
+            } else {
+                self.remove_node(root);
+            }
+        }
+    }
+
+    /// Removes a node, attaching its children to the node's parent.
+    fn remove_node(&mut self, i: usize) {
+        let parent = self.nodes[i].parent;
+        let child = self.nodes.iter().position(|n| n.parent == Some(i));
+        self.nodes.remove(i);
+        let fix = |n: usize| if n > i { n - 1 } else { n };
+        for node in &mut self.nodes {
+            if node.parent == Some(i) {
+                node.parent = parent;
+            }
+            if node.redo == Some(i) {
+                node.redo = child;
+            }
+            node.parent = node.parent.map(fix);
+            node.redo = node.redo.map(fix);
+        }
+        self.pos = fix(self.pos);
+    }
+}
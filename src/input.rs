@@ -25,14 +25,28 @@ pub enum Action {
     Settings,
     Keys,
     Copy,
+    ImportCourse,
+    ExportCourse,
+    ExportImage,
+    CopyShareCode,
+    PasteShareCode,
+    Slots,
+    History,
+    GhostRace,
     Edit,
+    SaveReplay,
     Start,
     StepBack,
+    PlayBackward,
     Pause,
     StepForward,
     Play,
     FastForward,
     End,
+    PrevEvent,
+    NextEvent,
+    Slower,
+    Faster,
     Seek(usize),
 }
 
@@ -56,14 +70,28 @@ impl Action {
             Self::Settings => "Settings",
             Self::Keys => "Show/hide keyboard commands",
             Self::Copy => "Copy another level",
+            Self::ImportCourse => "Load course from file",
+            Self::ExportCourse => "Save course to file",
+            Self::ExportImage => "Export course image",
+            Self::CopyShareCode => "Copy share code",
+            Self::PasteShareCode => "Paste share code",
+            Self::Slots => "Save slots",
+            Self::History => "Undo history",
+            Self::GhostRace => "Preview race",
             Self::Edit => "Edit course",
+            Self::SaveReplay => "Save race to file",
             Self::Start => "Seek to start",
             Self::StepBack => "Step backward",
+            Self::PlayBackward => "Play backward",
             Self::Pause => "Pause",
             Self::StepForward => "Step forward",
             Self::Play => "Play",
             Self::FastForward => "Fast forward",
             Self::End => "Seek to end",
+            Self::PrevEvent => "Previous event",
+            Self::NextEvent => "Next event",
+            Self::Slower => "Slower playback",
+            Self::Faster => "Faster playback",
             Self::Seek(_) => "Seek",
         }
     }
@@ -83,16 +111,32 @@ impl Action {
             Self::Scroll(_)
                 | Self::Start
                 | Self::StepBack
+                | Self::PlayBackward
                 | Self::Pause
                 | Self::StepForward
                 | Self::Play
                 | Self::FastForward
                 | Self::End
+                | Self::PrevEvent
+                | Self::NextEvent
+                | Self::Slower
+                | Self::Faster
         )
     }
 
     pub const fn is_active_when_editing(&self) -> bool {
-        !matches!(self, Self::Start | Self::StepBack | Self::Pause | Self::End)
+        !matches!(
+            self,
+            Self::Start
+                | Self::StepBack
+                | Self::PlayBackward
+                | Self::Pause
+                | Self::End
+                | Self::PrevEvent
+                | Self::NextEvent
+                | Self::Slower
+                | Self::Faster
+        )
     }
 
     pub const fn can_start_sim(&self) -> bool {
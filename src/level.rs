@@ -1,7 +1,7 @@
 use std::hash::Hash;
 
 use enum_map::EnumMap;
-use serde::{Deserialize, Deserializer, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::tile::TileType;
 
@@ -16,20 +16,118 @@ fn deserialize_banned<'de, D: Deserializer<'de>>(
     Ok(ban_map)
 }
 
-#[derive(Deserialize, Clone)]
+fn serialize_banned<S: Serializer>(
+    banned: &EnumMap<TileType, bool>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let ban_list: Vec<TileType> = banned
+        .iter()
+        .filter_map(|(t, &b)| if b { Some(t) } else { None })
+        .collect();
+    ban_list.serialize(serializer)
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct LevelData {
     pub name: String,
     pub cars: usize,
     pub finish: Vec<usize>,
+    #[serde(default)]
     pub tutorial: Option<usize>,
-    #[serde(default, deserialize_with = "deserialize_banned")]
+    #[serde(
+        default,
+        deserialize_with = "deserialize_banned",
+        serialize_with = "serialize_banned"
+    )]
     pub banned: EnumMap<TileType, bool>,
+    #[serde(default)]
+    pub tutorial_text: Option<String>,
+    #[serde(default)]
+    pub max_rounds: Option<usize>,
+    #[serde(default)]
+    pub par: Option<SolveData>,
+}
+
+pub static MAX_CARS: usize = 1000;
+
+impl LevelData {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("the level has no name".to_string());
+        }
+        if self.banned[TileType::Finish] {
+            return Err("the start/finish line cannot be banned".to_string());
+        }
+        if self.max_rounds == Some(0) {
+            return Err("the round limit must be at least 1".to_string());
+        }
+        if self.cars > MAX_CARS {
+            return Err(format!("a level can have at most {MAX_CARS} cars"));
+        }
+        let mut seen = vec![false; self.cars];
+        for &n in &self.finish {
+            match seen.get_mut(n) {
+                None => {
+                    return Err(format!(
+                        "car {n} is in the finish order, but the level only has {} cars",
+                        self.cars
+                    ));
+                }
+                Some(&mut true) => {
+                    return Err(format!("car {n} appears twice in the finish order"));
+                }
+                Some(s) => *s = true,
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the two levels have the same rules, in which case their
+    /// courses and records are saved under the same keys.
+    pub fn same_rules(&self, other: &Self) -> bool {
+        self.cars == other.cars
+            && self.finish == other.finish
+            && self.max_rounds == other.max_rounds
+            && self.banned == other.banned
+    }
+
+    pub fn is_solved_by(&self, finishes: &[usize], solve: &SolveData) -> bool {
+        self.finish == finishes && self.max_rounds.is_none_or(|m| solve.turns <= m)
+    }
 }
 
-#[derive(Serialize, Deserialize, Copy, Clone)]
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Default)]
 pub struct SolveData {
     pub tiles: usize,
     pub turns: usize,
+    // The remaining metrics are `None` in solves saved before they were
+    // recorded.
+    #[serde(default)]
+    pub footprint: Option<usize>,
+    #[serde(default)]
+    pub cost: Option<usize>,
+    #[serde(default)]
+    pub intersections: Option<usize>,
+    #[serde(default)]
+    pub distance: Option<usize>,
+}
+
+/// The smaller of two metrics, ignoring a missing one.
+fn min_known(a: Option<usize>, b: Option<usize>) -> Option<usize> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        _ => a.or(b),
+    }
+}
+
+/// Whether `a` is at most `b`.  A missing metric is no better than any
+/// known one, and no worse than another missing one.
+fn at_most(a: Option<usize>, b: Option<usize>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a <= b,
+        (None, Some(_)) => false,
+        (_, None) => true,
+    }
 }
 
 impl SolveData {
@@ -37,8 +135,25 @@ impl SolveData {
         Self {
             tiles: self.tiles.min(other.tiles),
             turns: self.turns.min(other.turns),
+            footprint: min_known(self.footprint, other.footprint),
+            cost: min_known(self.cost, other.cost),
+            intersections: min_known(self.intersections, other.intersections),
+            distance: min_known(self.distance, other.distance),
         }
     }
+    /// Whether this solve is at least as good as `other` in every metric.  A
+    /// solve that is missing a metric that `other` has does not dominate it.
+    pub fn dominates(&self, other: &Self) -> bool {
+        self.tiles <= other.tiles
+            && self.turns <= other.turns
+            && at_most(self.footprint, other.footprint)
+            && at_most(self.cost, other.cost)
+            && at_most(self.intersections, other.intersections)
+            && at_most(self.distance, other.distance)
+    }
+    pub fn is_better_than(&self, other: &Self) -> bool {
+        (self.tiles, self.turns) < (other.tiles, other.turns)
+    }
     pub fn combine_option(&self, other: &Option<Self>) -> Self {
         match other {
             Some(d) => self.combine(d),
@@ -51,5 +166,9 @@ impl Hash for LevelData {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.cars.hash(state);
         self.finish.hash(state);
+        if let Some(m) = self.max_rounds {
+            m.hash(state);
+        }
+        self.banned.hash(state);
     }
 }
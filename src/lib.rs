@@ -1,17 +1,28 @@
 pub mod combine;
 pub mod course;
 pub mod direction;
+pub mod finish;
+pub mod ghost;
+pub mod history;
 pub mod input;
 pub mod level;
+pub mod metrics;
+pub mod pack;
 pub mod path;
 pub mod playback;
+pub mod records;
+pub mod render;
+pub mod replay_file;
 pub mod save;
 pub mod selection;
+pub mod share;
 pub mod simulator;
 pub mod states;
+pub mod svg;
 pub mod tile;
 pub mod tooltip;
 pub mod tracker;
+pub mod traffic;
 pub mod ui;
 
 #[cfg(test)]
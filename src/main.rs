@@ -2,7 +2,7 @@ use notan::draw::DrawConfig;
 use notan::egui::{EguiConfig, EguiPluginSugar};
 use notan::extra::FpsLimit;
 use notan::prelude::*;
-use race::save::{load_or_log_err, save_or_log_err};
+use race::save::{load_or_log_err, migrate_storage, save_or_log_err};
 use race::states::SelectState;
 use race::ui::loader::Resources;
 use race::ui::menu::apply_zoom_settings;
@@ -61,8 +61,9 @@ fn adjust_font_sizes(gfx: &mut Graphics, plugins: &mut Plugins) {
     gfx.render(&output);
 }
 
-fn event(app: &mut App, event: Event) {
+fn event(app: &mut App, data: &mut GameData, event: Event) {
     if matches!(event, Event::Exit) {
+        data.state.save_on_exit();
         let window_size = if app.window().is_fullscreen() {
             None
         } else {
@@ -74,6 +75,7 @@ fn event(app: &mut App, event: Event) {
 
 fn init(gfx: &mut Graphics, plugins: &mut Plugins) -> GameData {
     adjust_font_sizes(gfx, plugins);
+    migrate_storage();
     let resources = Resources::load_all(gfx);
 
     let state: Box<dyn Screen> = Box::new(SelectState::new(&resources.levels));
@@ -0,0 +1,122 @@
+use serde::{Deserialize, Serialize};
+use strum::{EnumIter, IntoEnumIterator};
+
+use crate::{
+    course::{Course, bounding_rect},
+    level::SolveData,
+    simulator::CarCoord,
+    tile::TileType,
+    tracker::Tracker,
+};
+
+/// A measure of how much space or hardware a course uses.  Records and best
+/// courses are ranked by the chosen metric together with the number of
+/// rounds.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumIter, Debug, Default)]
+pub enum Metric {
+    #[default]
+    Tiles,
+    Footprint,
+    Cost,
+    Intersections,
+    Distance,
+}
+
+impl Metric {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Tiles => "Tiles",
+            Self::Footprint => "Footprint",
+            Self::Cost => "Cost",
+            Self::Intersections => "Intersections",
+            Self::Distance => "Distance",
+        }
+    }
+
+    pub const fn description(self) -> &'static str {
+        match self {
+            Self::Tiles => "Number of tiles",
+            Self::Footprint => "Area of the bounding box of the course",
+            Self::Cost => "Tile count weighted by tile type",
+            Self::Intersections => "Number of intersections",
+            Self::Distance => "Total distance driven by all cars",
+        }
+    }
+
+    /// The value of the metric, or `None` if the solve predates it.
+    pub const fn value(self, solve: &SolveData) -> Option<usize> {
+        match self {
+            Self::Tiles => Some(solve.tiles),
+            Self::Footprint => solve.footprint,
+            Self::Cost => solve.cost,
+            Self::Intersections => solve.intersections,
+            Self::Distance => solve.distance,
+        }
+    }
+
+    pub fn value_text(self, solve: &SolveData) -> String {
+        self.value(solve).map_or("-".to_string(), |v| v.to_string())
+    }
+}
+
+/// The number of rows times the number of columns spanned by the course.
+pub fn footprint(course: &Course) -> usize {
+    if course.is_empty() {
+        return 0;
+    }
+    let (xrange, yrange) = bounding_rect(course.keys());
+    xrange.count() * yrange.count()
+}
+
+pub fn cost(course: &Course) -> usize {
+    course.values().map(|t| t.tile_type.cost()).sum()
+}
+
+pub fn intersections(course: &Course) -> usize {
+    course
+        .values()
+        .filter(|t| {
+            matches!(
+                t.tile_type,
+                TileType::YieldIntersection | TileType::LightIntersection
+            )
+        })
+        .count()
+}
+
+/// The total number of tiles driven by all cars over the whole race.
+pub fn distance(tracker: &Tracker) -> usize {
+    let mut last: Vec<Option<CarCoord>> = Vec::new();
+    let mut half_steps = 0;
+    for round in tracker.get_cars() {
+        for car in round {
+            if car.id >= last.len() {
+                last.resize(car.id + 1, None);
+            }
+            if let Some(prev) = last[car.id] {
+                half_steps += prev.0.abs_diff(car.pos.0) + prev.1.abs_diff(car.pos.1);
+            }
+            last[car.id] = Some(car.pos);
+        }
+    }
+    half_steps / 2
+}
+
+/// Computes all of the metrics for a finished race.
+pub fn measure(course: &Course, tracker: &Tracker) -> SolveData {
+    SolveData {
+        tiles: course.size(),
+        turns: tracker.rounds_available() - 1,
+        footprint: Some(footprint(course)),
+        cost: Some(cost(course)),
+        intersections: Some(intersections(course)),
+        distance: Some(distance(tracker)),
+    }
+}
+
+/// One line per metric, for example "Footprint: 24".
+pub fn summary(solve: &SolveData) -> String {
+    let mut lines = vec![format!("Rounds: {}", solve.turns)];
+    lines.extend(Metric::iter().map(|m| format!("{}: {}", m.name(), m.value_text(solve))));
+    lines.join("\n")
+}
@@ -0,0 +1,163 @@
+use std::rc::Rc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::level::LevelData;
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct PackMeta {
+    pub name: String,
+    #[serde(default)]
+    pub author: String,
+    #[serde(default)]
+    pub version: String,
+}
+
+pub static MAIN_PACK: &str = "Main";
+
+pub struct LevelPack {
+    pub meta: PackMeta,
+    pub levels: Vec<Rc<LevelData>>,
+}
+
+#[derive(Deserialize)]
+struct PackFile {
+    pack: Option<PackMeta>,
+    levels: Vec<LevelData>,
+}
+
+/// Parses and validates a level pack.  Packs without a `[pack]` table are
+/// given the name `default_name`.
+pub fn parse_pack(text: &str, default_name: &str) -> Result<LevelPack, String> {
+    let file: PackFile = toml::from_str(text).map_err(|e| e.to_string())?;
+    let meta = file.pack.unwrap_or_else(|| PackMeta {
+        name: default_name.to_string(),
+        ..Default::default()
+    });
+    if meta.name.trim().is_empty() {
+        return Err("the pack has no name".to_string());
+    }
+    if file.levels.is_empty() {
+        return Err("the pack contains no levels".to_string());
+    }
+    for (n, lvl) in file.levels.iter().enumerate() {
+        lvl.validate()
+            .map_err(|e| format!("level {} (\"{}\"): {e}", n + 1, lvl.name))?;
+    }
+    Ok(LevelPack {
+        meta,
+        levels: file.levels.into_iter().map(Rc::new).collect(),
+    })
+}
+
+#[derive(Serialize)]
+struct LevelList<'a> {
+    levels: &'a [LevelData],
+}
+
+/// Writes levels as `[[levels]]` tables, in the format used by
+/// `res/levels.toml` and by level packs.
+pub fn levels_to_toml(levels: &[LevelData]) -> String {
+    toml::to_string(&LevelList { levels }).unwrap()
+}
+
+pub fn builtin_pack() -> LevelPack {
+    parse_pack(include_str!("../res/levels.toml"), MAIN_PACK).expect("built-in levels are invalid")
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use native::{install_pack, load_user_packs};
+
+#[cfg(target_arch = "wasm32")]
+pub use web::{install_pack, load_user_packs};
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+    use std::path::PathBuf;
+
+    use directories::ProjectDirs;
+
+    use super::{LevelPack, PackMeta, parse_pack};
+
+    fn pack_dir() -> Option<PathBuf> {
+        ProjectDirs::from("", "dgulotta", "race-game").map(|d| d.data_dir().join("packs"))
+    }
+
+    fn file_name_for(name: &str) -> String {
+        let stem: String = name
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '_' })
+            .collect();
+        format!("{stem}.toml")
+    }
+
+    /// Loads every `.toml` file in the user's pack directory.  Packs that
+    /// fail to load are reported as errors prefixed with the file name.
+    pub fn load_user_packs() -> Vec<Result<LevelPack, String>> {
+        let Some(dir) = pack_dir() else {
+            return Vec::new();
+        };
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            return Vec::new();
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|ext| ext == "toml"))
+            .collect();
+        paths.sort();
+        paths
+            .into_iter()
+            .map(|path| {
+                let file = path.file_name().unwrap_or_default().to_string_lossy();
+                let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                std::fs::read_to_string(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|text| parse_pack(&text, &stem))
+                    .map_err(|e| format!("{file}: {e}"))
+            })
+            .collect()
+    }
+
+    /// Validates a pack and copies it into the user's pack directory.
+    pub fn install_pack(text: &str) -> Result<PackMeta, String> {
+        let pack = parse_pack(text, "Imported pack")?;
+        let dir = pack_dir().ok_or("could not find the pack directory")?;
+        std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        std::fs::write(dir.join(file_name_for(&pack.meta.name)), text)
+            .map_err(|e| e.to_string())?;
+        Ok(pack.meta)
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod web {
+    use crate::save::{load_or_log_err, save_or_log_err};
+
+    use super::{LevelPack, PackMeta, parse_pack};
+
+    static DEFAULT_NAME: &str = "Imported pack";
+
+    fn load_pack_texts() -> Vec<String> {
+        load_or_log_err("packs", "Failed to load level packs").unwrap_or_default()
+    }
+
+    pub fn load_user_packs() -> Vec<Result<LevelPack, String>> {
+        load_pack_texts()
+            .iter()
+            .map(|text| parse_pack(text, DEFAULT_NAME))
+            .collect()
+    }
+
+    /// Validates a pack and stores it in browser storage, replacing any
+    /// stored pack with the same name.
+    pub fn install_pack(text: &str) -> Result<PackMeta, String> {
+        let pack = parse_pack(text, DEFAULT_NAME)?;
+        let mut texts = load_pack_texts();
+        texts.retain(|t| {
+            parse_pack(t, DEFAULT_NAME).map_or(true, |p| p.meta.name != pack.meta.name)
+        });
+        texts.push(text.to_string());
+        save_or_log_err("packs", &texts, "Failed to save level packs");
+        Ok(pack.meta)
+    }
+}
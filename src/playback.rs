@@ -3,8 +3,12 @@ use std::time::Duration;
 use notan::math::Vec2;
 
 use crate::{
+    course::TileCoord,
     direction::Direction,
+    input::Action,
     simulator::{CarCoord, CarData},
+    tracker::Tracker,
+    traffic::Divergence,
 };
 
 #[derive(Clone, Copy, Eq, PartialEq, Debug)]
@@ -12,16 +16,312 @@ pub enum Playback {
     Paused,
     Playing,
     Fast,
+    Backward,
 }
 
+pub const MIN_SPEED: f32 = 0.1;
+pub const MAX_SPEED: f32 = 100.0;
+/// The factor that the faster and slower keys change the speed by.
+const SPEED_STEP: f32 = 1.5;
+
 impl Playback {
     pub const fn frame_duration(self) -> Duration {
         match self {
             Self::Paused => Duration::from_millis(100),
-            Self::Playing => Duration::from_millis(500),
+            Self::Playing | Self::Backward => Duration::from_millis(500),
             Self::Fast => Duration::from_millis(100),
         }
     }
+
+    /// How long each round is shown for when playing at `speed` times the
+    /// normal rate.  Single steps while paused aren't affected by the speed.
+    pub fn round_duration(self, speed: f32) -> Duration {
+        match self {
+            Self::Paused => self.frame_duration(),
+            _ => self.frame_duration().div_f32(speed),
+        }
+    }
+}
+
+pub fn faster(speed: f32) -> f32 {
+    (speed * SPEED_STEP).min(MAX_SPEED)
+}
+
+pub fn slower(speed: f32) -> f32 {
+    (speed / SPEED_STEP).max(MIN_SPEED)
+}
+
+/// The number of rounds that should have been shown after `elapsed`.  This
+/// is more than one when rounds are shorter than a frame, in which case the
+/// rounds in between are skipped.
+pub fn rounds_due(elapsed: Duration, round_duration: Duration) -> u32 {
+    (elapsed.as_secs_f64() / round_duration.as_secs_f64()) as u32
+}
+
+/// A condition that pauses playback when the race reaches it.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Breakpoint {
+    Spawn(usize),
+    Finish(usize),
+    Crash(usize),
+    EnterTile(TileCoord),
+    /// The first round in which the finish order differs from the goal.
+    Divergence,
+}
+
+fn car_in(tracker: &Tracker, round: usize, id: usize) -> Option<&CarData> {
+    tracker.get_cars()[round].iter().find(|c| c.id == id)
+}
+
+/// Whether the car with the given id left the course between `round - 1`
+/// and `round`.
+fn car_left(tracker: &Tracker, round: usize, id: usize) -> bool {
+    round > 0 && car_in(tracker, round - 1, id).is_some() && car_in(tracker, round, id).is_none()
+}
+
+impl Breakpoint {
+    pub fn description(&self) -> String {
+        match self {
+            Self::Spawn(id) => format!("Car {id} spawns"),
+            Self::Finish(id) => format!("Car {id} finishes"),
+            Self::Crash(id) => format!("Car {id} crashes"),
+            Self::EnterTile(pos) => format!("A car enters ({}, {})", pos.0, pos.1),
+            Self::Divergence => "Finish order diverges".to_string(),
+        }
+    }
+
+    /// Whether the breakpoint is reached when the race advances from
+    /// `round - 1` to `round`.
+    pub fn is_hit(&self, tracker: &Tracker, round: usize, divergence_round: Option<usize>) -> bool {
+        let finished = |id| tracker.get_finishes().contains(&id);
+        match *self {
+            Self::Spawn(id) => {
+                car_in(tracker, round, id).is_some()
+                    && (round == 0 || car_in(tracker, round - 1, id).is_none())
+            }
+            Self::Finish(id) => car_left(tracker, round, id) && finished(id),
+            Self::Crash(id) => car_left(tracker, round, id) && !finished(id),
+            Self::EnterTile(pos) => tracker.get_cars()[round].iter().any(|car| {
+                TileCoord::from(car.tile_pos()) == pos
+                    && (round == 0
+                        || car_in(tracker, round - 1, car.id)
+                            .is_none_or(|prev| TileCoord::from(prev.tile_pos()) != pos))
+            }),
+            Self::Divergence => divergence_round == Some(round),
+        }
+    }
+}
+
+/// Whether any car spawned, finished or crashed between `round - 1` and
+/// `round`.
+fn cars_changed(tracker: &Tracker, round: usize) -> bool {
+    let cars = tracker.get_cars();
+    round > 0 && {
+        let ids = |r: usize| {
+            cars[r]
+                .iter()
+                .map(|c| c.id)
+                .collect::<hashbrown::HashSet<_>>()
+        };
+        ids(round - 1) != ids(round)
+    }
+}
+
+fn is_event(
+    tracker: &Tracker,
+    breakpoints: &[Breakpoint],
+    round: usize,
+    divergence_round: Option<usize>,
+) -> bool {
+    if breakpoints.is_empty() {
+        cars_changed(tracker, round)
+    } else {
+        breakpoints
+            .iter()
+            .any(|b| b.is_hit(tracker, round, divergence_round))
+    }
+}
+
+/// The first round after `round` that hits a breakpoint.  If there are no
+/// breakpoints, any spawn, finish or crash counts as an event.
+pub fn next_event(
+    tracker: &Tracker,
+    breakpoints: &[Breakpoint],
+    round: usize,
+    divergence_round: Option<usize>,
+) -> Option<usize> {
+    (round + 1..tracker.rounds_available())
+        .find(|&r| is_event(tracker, breakpoints, r, divergence_round))
+}
+
+/// The last round before `round` that hits a breakpoint, in the same way
+/// as `next_event`.
+pub fn prev_event(
+    tracker: &Tracker,
+    breakpoints: &[Breakpoint],
+    round: usize,
+    divergence_round: Option<usize>,
+) -> Option<usize> {
+    (1..round)
+        .rev()
+        .find(|&r| is_event(tracker, breakpoints, r, divergence_round))
+}
+
+/// The playback of a race whose rounds have all been simulated, shared by
+/// the race screen and the replay viewer.
+pub struct Player {
+    pub playback: Playback,
+    /// A multiplier for the playback rate.
+    pub speed: f32,
+    pub round: usize,
+    pub animations: Vec<CarAnimation>,
+    /// The round that the animations start from.
+    pub animation_round: usize,
+    pub round_display_time: Duration,
+    pub breakpoints: Vec<Breakpoint>,
+    pub divergence: Option<Divergence>,
+}
+
+impl Default for Player {
+    fn default() -> Self {
+        Self {
+            playback: Playback::Paused,
+            speed: 1.0,
+            round: 0,
+            animations: Vec::new(),
+            animation_round: 0,
+            round_display_time: Duration::ZERO,
+            breakpoints: Vec::new(),
+            divergence: None,
+        }
+    }
+}
+
+impl Player {
+    pub fn round_duration(&self) -> Duration {
+        self.playback.round_duration(self.speed)
+    }
+
+    pub fn cars<'a>(&self, tracker: &'a Tracker) -> &'a Vec<CarData> {
+        &tracker.get_cars()[self.round]
+    }
+
+    fn divergence_round(&self) -> Option<usize> {
+        self.divergence.map(|d| d.round)
+    }
+
+    pub fn forward(&mut self, tracker: &Tracker, animate: bool) {
+        if self.round + 1 < tracker.rounds_available() {
+            self.round += 1;
+            if animate {
+                let cars = tracker.get_cars();
+                self.animations = animations(&cars[self.round - 1], &cars[self.round]);
+                self.animation_round = self.round - 1;
+            } else {
+                self.animations.clear();
+            }
+        }
+    }
+
+    pub fn backward(&mut self, tracker: &Tracker, animate: bool) {
+        if self.round > 0 {
+            self.round -= 1;
+            if animate {
+                let cars = tracker.get_cars();
+                self.animations = reverse_animations(&cars[self.round + 1], &cars[self.round]);
+                self.animation_round = self.round + 1;
+            } else {
+                self.animations.clear();
+            }
+        }
+    }
+
+    /// Pauses, after jumping to `round` if there is one.
+    fn seek(&mut self, round: Option<usize>) {
+        if let Some(r) = round {
+            self.round = r;
+        }
+        self.animations.clear();
+        self.playback = Playback::Paused;
+    }
+
+    /// Carries out the playback actions and ignores any others.
+    pub fn process_command(&mut self, command: Action, time: Duration, tracker: &Tracker) {
+        self.round_display_time = time;
+        let last_round = tracker.rounds_available() - 1;
+        match command {
+            Action::Seek(n) => {
+                self.round = n.min(last_round);
+                self.animations.clear();
+            }
+            Action::Start => self.seek(Some(0)),
+            Action::StepBack => {
+                self.backward(tracker, true);
+                self.playback = Playback::Paused;
+            }
+            Action::PlayBackward => self.playback = Playback::Backward,
+            Action::Pause => self.seek(None),
+            Action::Play => self.playback = Playback::Playing,
+            Action::StepForward => {
+                self.forward(tracker, true);
+                self.playback = Playback::Paused;
+            }
+            Action::FastForward => self.playback = Playback::Fast,
+            Action::Slower => self.speed = slower(self.speed),
+            Action::Faster => self.speed = faster(self.speed),
+            Action::End => self.seek(Some(last_round)),
+            Action::NextEvent => self.seek(next_event(
+                tracker,
+                &self.breakpoints,
+                self.round,
+                self.divergence_round(),
+            )),
+            Action::PrevEvent => self.seek(prev_event(
+                tracker,
+                &self.breakpoints,
+                self.round,
+                self.divergence_round(),
+            )),
+            _ => (),
+        }
+    }
+
+    /// Moves through the rounds that are due by `time`.  Playback pauses at
+    /// a breakpoint and at either end of the race.
+    pub fn check_advance(&mut self, time: Duration, tracker: &Tracker) {
+        if self.playback != Playback::Paused {
+            let duration = self.round_duration();
+            let due = rounds_due(time - self.round_display_time, duration);
+            self.round_display_time += duration * due;
+            for n in 1..=due {
+                let old_round = self.round;
+                let backward = self.playback == Playback::Backward;
+                if backward {
+                    self.backward(tracker, n == due);
+                } else {
+                    self.forward(tracker, n == due);
+                }
+                // Going backward passes the events of the round it leaves.
+                let event_round = if backward { old_round } else { self.round };
+                if self.round == old_round
+                    || self
+                        .breakpoints
+                        .iter()
+                        .any(|b| b.is_hit(tracker, event_round, self.divergence_round()))
+                {
+                    self.playback = Playback::Paused;
+                    break;
+                }
+            }
+        }
+        let at_end = match self.playback {
+            Playback::Backward => self.round == 0,
+            _ => self.round == tracker.rounds_available() - 1,
+        };
+        if at_end {
+            self.playback = Playback::Paused;
+        }
+    }
 }
 
 #[derive(Clone, Copy, Eq, PartialEq)]
@@ -34,6 +334,8 @@ pub struct CarAnimation {
     pub id: usize,
     pub old_pos: Option<CarPosition>,
     pub new_pos: CarPosition,
+    /// Plays the movement from `old_pos` to `new_pos` backwards.
+    pub reversed: bool,
 }
 
 pub struct AnimationData {
@@ -76,6 +378,7 @@ impl CarAnimation {
         match &self.old_pos {
             Some(old) if old != &self.new_pos => {
                 let t = time.clamp(0.0, 1.0);
+                let t = if self.reversed { 1.0 - t } else { t };
                 let tc = 1.0 - t;
                 let p0 = Self::car_pos_vec(old.pos);
                 let p1 = Self::car_pos_vec(old.pos.add_multiple(old.dir, 1));
@@ -112,6 +415,7 @@ pub fn animations(old: &[CarData], new: &[CarData]) -> Vec<CarAnimation> {
                         pos: c.pos,
                         dir: c.dir,
                     },
+                    reversed: false,
                 },
             )
         })
@@ -126,3 +430,43 @@ pub fn animations(old: &[CarData], new: &[CarData]) -> Vec<CarAnimation> {
     }
     cars.into_values().collect()
 }
+
+/// The animations for stepping back from `old` to the previous round `new`.
+/// Each car in `new` drives backwards along the path it took; cars that
+/// spawned in `old` disappear.
+pub fn reverse_animations(old: &[CarData], new: &[CarData]) -> Vec<CarAnimation> {
+    let old_pos: hashbrown::HashMap<_, _> = old
+        .iter()
+        .map(|c| {
+            (
+                c.id,
+                CarPosition {
+                    pos: c.pos,
+                    dir: c.dir,
+                },
+            )
+        })
+        .collect();
+    new.iter()
+        .map(|c| {
+            let pos = CarPosition {
+                pos: c.pos,
+                dir: c.dir,
+            };
+            match old_pos.get(&c.id) {
+                Some(&later) => CarAnimation {
+                    id: c.id,
+                    old_pos: Some(pos),
+                    new_pos: later,
+                    reversed: true,
+                },
+                None => CarAnimation {
+                    id: c.id,
+                    old_pos: None,
+                    new_pos: pos,
+                    reversed: false,
+                },
+            }
+        })
+        .collect()
+}
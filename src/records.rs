@@ -0,0 +1,196 @@
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
+
+use crate::{
+    course::Course,
+    level::{LevelData, SolveData},
+    metrics::{Metric, measure},
+    simulator::Simulator,
+    tracker::Tracker,
+};
+
+/// A successful run, as recorded in the level's run history.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct RunRecord {
+    pub solve: SolveData,
+    pub date: DateTime<Utc>,
+}
+
+impl RunRecord {
+    pub fn now(solve: SolveData) -> Self {
+        Self {
+            solve,
+            date: Utc::now(),
+        }
+    }
+
+    pub fn date_string(&self) -> String {
+        self.date
+            .with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M")
+            .to_string()
+    }
+}
+
+/// A course that is not beaten in both tiles and turns by any other course
+/// the player has built for the level.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ParetoEntry {
+    pub solve: SolveData,
+    pub course: Course,
+}
+
+/// Adds a course to a Pareto front that is sorted by tiles.  Only entries
+/// that are among the `best_courses` for some metric are kept, so the front
+/// stays small.  Returns false, leaving the front unchanged, if the new
+/// course would not be kept.
+pub fn add_to_pareto_front(front: &mut Vec<ParetoEntry>, entry: ParetoEntry) -> bool {
+    if front.iter().any(|e| e.solve.dominates(&entry.solve)) {
+        return false;
+    }
+    let pos = front.partition_point(|e| e.solve.tiles < entry.solve.tiles);
+    front.insert(pos, entry);
+    let mut keep = vec![false; front.len()];
+    for metric in Metric::iter() {
+        for e in best_courses(front, metric) {
+            if let Some(n) = front.iter().position(|f| std::ptr::eq(f, e)) {
+                keep[n] = true;
+            }
+        }
+    }
+    if !keep[pos] {
+        front.remove(pos);
+        return false;
+    }
+    let mut n = 0;
+    front.retain(|_| {
+        n += 1;
+        keep[n - 1]
+    });
+    true
+}
+
+/// The entry of the front that is best in `metric`, breaking ties by turns.
+pub fn best_entry(front: &[ParetoEntry], metric: Metric) -> Option<&ParetoEntry> {
+    best_courses(front, metric).into_iter().next()
+}
+
+/// The courses in the front that are not beaten in both `metric` and rounds,
+/// ordered by `metric`.  Courses that tie keep the first one found, and
+/// courses saved before `metric` was recorded are left out.
+pub fn best_courses(front: &[ParetoEntry], metric: Metric) -> Vec<&ParetoEntry> {
+    let mut sorted: Vec<&ParetoEntry> = front
+        .iter()
+        .filter(|e| metric.value(&e.solve).is_some())
+        .collect();
+    sorted.sort_by_key(|e| (metric.value(&e.solve), e.solve.turns));
+    let mut best_turns = usize::MAX;
+    sorted.retain(|e| {
+        let keep = e.solve.turns < best_turns;
+        best_turns = best_turns.min(e.solve.turns);
+        keep
+    });
+    sorted
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Medal {
+    Gold,
+    Silver,
+    Bronze,
+}
+
+impl Medal {
+    /// Gold means matching par in both `metric` and rounds.  Silver and bronze
+    /// allow each to be at most 25% and 50% over par.
+    pub fn for_solve(solve: &SolveData, par: &SolveData, metric: Metric) -> Option<Self> {
+        let (_, value, par_value) = compared_values(solve, par, metric);
+        let worst =
+            percent_over_par(value, par_value).max(percent_over_par(solve.turns, par.turns));
+        if worst <= 0.0 {
+            Some(Self::Gold)
+        } else if worst <= 25.0 {
+            Some(Self::Silver)
+        } else if worst <= 50.0 {
+            Some(Self::Bronze)
+        } else {
+            None
+        }
+    }
+
+    pub const fn icon(self) -> &'static str {
+        match self {
+            Self::Gold => "\u{1f947}",
+            Self::Silver => "\u{1f948}",
+            Self::Bronze => "\u{1f949}",
+        }
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Gold => "Gold",
+            Self::Silver => "Silver",
+            Self::Bronze => "Bronze",
+        }
+    }
+}
+
+pub fn percent_over_par(value: usize, par: usize) -> f32 {
+    if par == 0 {
+        if value == 0 { 0.0 } else { 100.0 }
+    } else {
+        100.0 * (value as f32 - par as f32) / par as f32
+    }
+}
+
+/// The values of `metric` for a solve and for par, falling back to tiles if
+/// either of them was recorded before the metric was.
+fn compared_values(solve: &SolveData, par: &SolveData, metric: Metric) -> (Metric, usize, usize) {
+    match (metric.value(solve), metric.value(par)) {
+        (Some(value), Some(par_value)) => (metric, value, par_value),
+        _ => (Metric::Tiles, solve.tiles, par.tiles),
+    }
+}
+
+/// Describes how a solve compares to par, for example
+/// "+20% tiles, par rounds".
+pub fn par_comparison(solve: &SolveData, par: &SolveData, metric: Metric) -> String {
+    let describe = |value, par, what: &str| {
+        let pct = percent_over_par(value, par);
+        if pct == 0.0 {
+            format!("par {what}")
+        } else {
+            format!("{pct:+.0}% {what}")
+        }
+    };
+    let (metric, value, par_value) = compared_values(solve, par, metric);
+    format!(
+        "{}, {}",
+        describe(value, par_value, &metric.name().to_lowercase()),
+        describe(solve.turns, par.turns, "rounds")
+    )
+}
+
+/// Runs a course until all cars have finished or crashed, or the race gets
+/// stuck in a loop.
+pub fn run_race(course: &Course, cars: usize) -> Tracker {
+    let mut tracker = Tracker::new(cars);
+    let mut sim = Simulator::new(course.clone(), cars);
+    while !(sim.is_finished() || tracker.is_loop_detected()) {
+        sim.run_round();
+        for ev in sim.events() {
+            tracker.process_event(ev);
+        }
+    }
+    tracker
+}
+
+/// Runs a course to completion, returning its solve data if the cars finish
+/// in the order required by the level.
+pub fn simulate(lvl: &LevelData, course: &Course) -> Option<SolveData> {
+    let tracker = run_race(course, lvl.cars);
+    let solve = measure(course, &tracker);
+    lvl.is_solved_by(tracker.get_finishes(), &solve)
+        .then_some(solve)
+}
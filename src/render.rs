@@ -0,0 +1,459 @@
+//! A software backend for `TileGraphics` that draws courses and cars into
+//! an RGBA buffer, for exporting images without a graphics context.
+
+use ab_glyph::{Font, FontArc, ScaleFont, point};
+use enum_map::EnumMap;
+use notan::math::{Mat3, Vec2};
+use notan::prelude::Color;
+
+use crate::{
+    course::{Course, bounding_rect},
+    playback::animations,
+    tile::TileType,
+    tracker::Tracker,
+    ui::{
+        graphics::{Canvas, TILE_SIZE, TileGraphics},
+        loader::{CAR_IMAGES, font_bytes, load_tile_type},
+    },
+};
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl RgbaImage {
+    pub fn new(width: u32, height: u32, color: [u8; 4]) -> Self {
+        Self {
+            width,
+            height,
+            data: color.repeat((width as usize) * (height as usize)),
+        }
+    }
+
+    pub fn from_png(bytes: &[u8]) -> Result<Self, png::DecodingError> {
+        let mut decoder = png::Decoder::new(bytes);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info()?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf)?;
+        buf.truncate(info.buffer_size());
+        let data = match info.color_type {
+            png::ColorType::Rgba => buf,
+            png::ColorType::Rgb => buf
+                .chunks_exact(3)
+                .flat_map(|p| [p[0], p[1], p[2], 0xFF])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => buf
+                .chunks_exact(2)
+                .flat_map(|p| [p[0], p[0], p[0], p[1]])
+                .collect(),
+            png::ColorType::Grayscale => buf.iter().flat_map(|&g| [g, g, g, 0xFF]).collect(),
+            png::ColorType::Indexed => unreachable!("indexed images are expanded"),
+        };
+        Ok(Self {
+            width: info.width,
+            height: info.height,
+            data,
+        })
+    }
+
+    pub fn to_png(&self) -> Result<Vec<u8>, png::EncodingError> {
+        let mut out = Vec::new();
+        let mut encoder = png::Encoder::new(&mut out, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.data)?;
+        writer.finish()?;
+        Ok(out)
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        4 * ((y as usize) * (self.width as usize) + (x as usize))
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = self.index(x, y);
+        [
+            self.data[i],
+            self.data[i + 1],
+            self.data[i + 2],
+            self.data[i + 3],
+        ]
+    }
+
+    /// Draws a pixel over the existing one using its alpha channel.
+    fn blend(&mut self, x: u32, y: u32, src: [u8; 4]) {
+        let i = self.index(x, y);
+        let alpha = src[3] as u32;
+        for (dst, &c) in self.data[i..i + 3].iter_mut().zip(&src) {
+            *dst = ((c as u32 * alpha + *dst as u32 * (255 - alpha) + 127) / 255) as u8;
+        }
+        let dst_alpha = self.data[i + 3] as u32;
+        self.data[i + 3] = (alpha + (dst_alpha * (255 - alpha) + 127) / 255) as u8;
+    }
+
+    /// The pixel nearest to the point `(u, v)`, where both coordinates are
+    /// between 0 and 1.
+    fn sample(&self, u: f32, v: f32) -> Option<[u8; 4]> {
+        if !(0.0..1.0).contains(&u) || !(0.0..1.0).contains(&v) {
+            return None;
+        }
+        let x = ((u * self.width as f32) as u32).min(self.width - 1);
+        let y = ((v * self.height as f32) as u32).min(self.height - 1);
+        Some(self.pixel(x, y))
+    }
+}
+
+/// The tile and car images, decoded from the same files as the textures in
+/// `Resources`, and the font that is used for text.
+pub struct Sprites {
+    pub tiles: EnumMap<TileType, [RgbaImage; 2]>,
+    pub cars: Vec<RgbaImage>,
+    pub font: FontArc,
+}
+
+impl Sprites {
+    pub fn load() -> Self {
+        let decode = |bytes| RgbaImage::from_png(bytes).expect("built-in image is valid");
+        Self {
+            tiles: EnumMap::from_fn(|t| {
+                let (first, second) = load_tile_type(t);
+                let first = decode(first);
+                let second = second.map_or_else(|| first.clone(), decode);
+                [first, second]
+            }),
+            cars: CAR_IMAGES.iter().map(|&b| decode(b)).collect(),
+            font: FontArc::try_from_vec(font_bytes().into_owned()).expect("built-in font is valid"),
+        }
+    }
+}
+
+fn to_rgba8(color: Color) -> [u8; 4] {
+    [color.r, color.g, color.b, color.a].map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8)
+}
+
+/// Lays out a line of text in an image just large enough to hold it.
+fn text_image(font: &FontArc, text: &str, px: f32, fg: [u8; 4], bg: [u8; 4]) -> RgbaImage {
+    let font = font.as_scaled(px);
+    let mut glyphs = Vec::new();
+    let mut x = 0.0;
+    let mut last = None;
+    for c in text.chars() {
+        let id = font.glyph_id(c);
+        if let Some(prev) = last {
+            x += font.kern(prev, id);
+        }
+        glyphs.push(id.with_scale_and_position(px, point(x, font.ascent())));
+        x += font.h_advance(id);
+        last = Some(id);
+    }
+    let mut image = RgbaImage::new(x.ceil() as u32, font.height().ceil() as u32, bg);
+    for glyph in glyphs {
+        let Some(outline) = font.outline_glyph(glyph) else {
+            continue;
+        };
+        let bounds = outline.px_bounds();
+        outline.draw(|gx, gy, coverage| {
+            let x = bounds.min.x as i32 + gx as i32;
+            let y = bounds.min.y as i32 + gy as i32;
+            if (0..image.width as i32).contains(&x) && (0..image.height as i32).contains(&y) {
+                let alpha = (fg[3] as f32 * coverage).round() as u8;
+                image.blend(x as u32, y as u32, [fg[0], fg[1], fg[2], alpha]);
+            }
+        });
+    }
+    image
+}
+
+/// The distance from `p` to the line segment from `a` to `b`.
+fn segment_distance(p: Vec2, a: Vec2, b: Vec2) -> f32 {
+    let ab = b - a;
+    let t = if ab == Vec2::ZERO {
+        0.0
+    } else {
+        ((p - a).dot(ab) / ab.length_squared()).clamp(0.0, 1.0)
+    };
+    p.distance(a + t * ab)
+}
+
+/// A `Canvas` that draws into an `RgbaImage`.  Shapes are not antialiased.
+pub struct SoftwareCanvas {
+    pub image: RgbaImage,
+    transforms: Vec<Mat3>,
+}
+
+impl SoftwareCanvas {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            image: RgbaImage::new(width, height, [0; 4]),
+            transforms: vec![Mat3::IDENTITY],
+        }
+    }
+
+    fn transform(&self) -> Mat3 {
+        *self.transforms.last().unwrap()
+    }
+
+    /// Blends `color(p)` into each pixel whose center is at the point `p`
+    /// of the rectangle from `min` to `max`, transformed by `transform`.
+    fn fill(
+        &mut self,
+        transform: Mat3,
+        min: Vec2,
+        max: Vec2,
+        color: impl Fn(Vec2) -> Option<[u8; 4]>,
+    ) {
+        let transform = self.transform() * transform;
+        let corners = [min, Vec2::new(max.x, min.y), Vec2::new(min.x, max.y), max]
+            .map(|p| transform.transform_point2(p));
+        let lo = corners
+            .into_iter()
+            .reduce(Vec2::min)
+            .unwrap()
+            .floor()
+            .max(Vec2::ZERO);
+        let hi = corners
+            .into_iter()
+            .reduce(Vec2::max)
+            .unwrap()
+            .ceil()
+            .max(Vec2::ZERO);
+        let inverse = transform.inverse();
+        for y in (lo.y as u32)..(hi.y as u32).min(self.image.height) {
+            for x in (lo.x as u32)..(hi.x as u32).min(self.image.width) {
+                let p = inverse.transform_point2(Vec2::new(x as f32 + 0.5, y as f32 + 0.5));
+                if p.cmpge(min).all()
+                    && p.cmplt(max).all()
+                    && let Some(c) = color(p)
+                {
+                    self.image.blend(x, y, c);
+                }
+            }
+        }
+    }
+
+    /// Draws a text image at the scale of the current transform, so that it
+    /// isn't blurred when the transform enlarges it.
+    fn draw_text_image(
+        &mut self,
+        res: &Sprites,
+        text: &str,
+        size: f32,
+        transform: Mat3,
+        colors: ([u8; 4], [u8; 4]),
+        centered: bool,
+    ) {
+        let scale = (self.transform() * transform).x_axis.truncate().length();
+        let image = text_image(&res.font, text, size * scale, colors.0, colors.1);
+        let image_size = Vec2::new(image.width as f32, image.height as f32) / scale;
+        let offset = if centered {
+            -0.5 * image_size
+        } else {
+            Vec2::ZERO
+        };
+        self.draw_sprite(
+            &image,
+            image_size,
+            transform * Mat3::from_translation(offset),
+            1.0,
+        );
+    }
+}
+
+impl Canvas for SoftwareCanvas {
+    type Sprite = RgbaImage;
+    type Resources = Sprites;
+
+    fn tile_sprite(res: &Sprites, tile_type: TileType, frame: usize) -> &RgbaImage {
+        &res.tiles[tile_type][frame]
+    }
+
+    fn car_sprite(res: &Sprites, color: usize) -> &RgbaImage {
+        &res.cars[color]
+    }
+
+    fn sprite_size(sprite: &RgbaImage) -> Vec2 {
+        Vec2::new(sprite.width as f32, sprite.height as f32)
+    }
+
+    fn push_transform(&mut self, transform: Mat3) {
+        self.transforms.push(self.transform() * transform);
+    }
+
+    fn pop_transform(&mut self) {
+        if self.transforms.len() > 1 {
+            self.transforms.pop();
+        }
+    }
+
+    fn clear_color(&mut self, color: Color) {
+        self.image = RgbaImage::new(self.image.width, self.image.height, to_rgba8(color));
+    }
+
+    fn draw_sprite(&mut self, sprite: &RgbaImage, size: Vec2, transform: Mat3, alpha: f32) {
+        self.fill(transform, Vec2::ZERO, size, |p| {
+            let [r, g, b, a] = sprite.sample(p.x / size.x, p.y / size.y)?;
+            Some([r, g, b, (a as f32 * alpha).round() as u8])
+        });
+    }
+
+    fn fill_rect(&mut self, pos: Vec2, size: Vec2, color: Color) {
+        let c = to_rgba8(color);
+        self.fill(Mat3::IDENTITY, pos, pos + size, |_| Some(c));
+    }
+
+    fn draw_line(&mut self, from: Vec2, to: Vec2, width: f32, color: Color) {
+        let c = to_rgba8(color);
+        let r = 0.5 * width;
+        let (min, max) = (from.min(to) - r, from.max(to) + r);
+        self.fill(Mat3::IDENTITY, min, max, |p| {
+            (segment_distance(p, from, to) <= r).then_some(c)
+        });
+    }
+
+    fn fill_triangle(&mut self, [a, b, c]: [Vec2; 3], color: Color) {
+        let rgba = to_rgba8(color);
+        let side = |p: Vec2, q: Vec2, r: Vec2| (q - p).perp_dot(r - p);
+        let (min, max) = (a.min(b).min(c), a.max(b).max(c));
+        self.fill(Mat3::IDENTITY, min, max, |p| {
+            let s = [side(a, b, p), side(b, c, p), side(c, a, p)];
+            (s.iter().all(|&v| v >= 0.0) || s.iter().all(|&v| v <= 0.0)).then_some(rgba)
+        });
+    }
+
+    fn fill_circle(&mut self, center: Vec2, radius: f32, color: Color) {
+        let c = to_rgba8(color);
+        self.fill(Mat3::IDENTITY, center - radius, center + radius, |p| {
+            (p.distance(center) <= radius).then_some(c)
+        });
+    }
+
+    fn stroke_circle(&mut self, center: Vec2, radius: f32, width: f32, color: Color) {
+        let c = to_rgba8(color);
+        let outer = radius + 0.5 * width;
+        self.fill(Mat3::IDENTITY, center - outer, center + outer, |p| {
+            ((p.distance(center) - radius).abs() <= 0.5 * width).then_some(c)
+        });
+    }
+
+    fn draw_label(&mut self, res: &Sprites, text: &str, size: f32, transform: Mat3) {
+        let colors = ([0, 0, 0, 0xFF], [0xFF; 4]);
+        self.draw_text_image(res, text, size, transform, colors, true);
+    }
+
+    fn draw_text(&mut self, res: &Sprites, text: &str, pos: Vec2, size: f32, color: Color) {
+        let colors = (to_rgba8(color), [0; 4]);
+        self.draw_text_image(res, text, size, Mat3::from_translation(pos), colors, false);
+    }
+}
+
+/// `TileGraphics` that draws into an image, for exporting courses and
+/// races without a graphics context.
+pub type SoftwareGraphics<'a> = TileGraphics<'a, SoftwareCanvas>;
+
+impl<'a> TileGraphics<'a, SoftwareCanvas> {
+    /// Creates an image large enough to show the course, where a tile is
+    /// `tile_size` pixels wide.  The width and height are rounded down to
+    /// even numbers, as WebP requires.
+    pub fn for_course(
+        sprites: &'a Sprites,
+        course: &Course,
+        tile_size: u32,
+        bg_color: &'a [f32; 3],
+    ) -> Self {
+        let (xrange, yrange) = if course.is_empty() {
+            (0..=0, 0..=0)
+        } else {
+            bounding_rect(course.keys())
+        };
+        let width = ((xrange.end() - xrange.start() + 1) as u32 * tile_size) & !0x1;
+        let height = ((yrange.end() - yrange.start() + 1) as u32 * tile_size) & !0x1;
+        let mut graphics = Self {
+            res: sprites,
+            zoom: tile_size as f32 / TILE_SIZE,
+            bg_color,
+            draw: SoftwareCanvas::new(width, height),
+            round: 0,
+        };
+        let origin = Vec2::new(*xrange.start() as f32, *yrange.start() as f32);
+        graphics.set_offset(&(-(tile_size as f32) * origin));
+        graphics
+    }
+}
+
+/// Renders a race in the same way as the replay export, calling `add_frame`
+/// for each frame.  The first frame shows the start of the race, and each
+/// later round is animated over `frames_per_round` frames.
+pub fn render_race<F>(
+    graphics: &mut SoftwareGraphics,
+    course: &Course,
+    tracker: &Tracker,
+    frames_per_round: usize,
+    mut add_frame: F,
+) -> Result<(), anyhow::Error>
+where
+    F: FnMut(&RgbaImage) -> Result<(), anyhow::Error>,
+{
+    let cars = tracker.get_cars();
+    for round in 0..cars.len() {
+        let (anims, steps) = match round {
+            0 => (Vec::new(), 1),
+            _ => (animations(&cars[round - 1], &cars[round]), frames_per_round),
+        };
+        for step in 1..=steps {
+            let moving = step < steps;
+            graphics.round = if moving { round - 1 } else { round };
+            graphics.draw_course(course);
+            if moving {
+                let t = step as f32 / steps as f32;
+                for anim in &anims {
+                    let pos = anim.position_at_time(t);
+                    graphics.draw_car_smooth(anim.id, &pos);
+                    graphics.draw_car_number_smooth(anim.id, &pos);
+                }
+            } else {
+                graphics.draw_cars(&cars[round], &[], 0.0);
+            }
+            add_frame(&graphics.draw.image)?;
+        }
+    }
+    Ok(())
+}
+
+/// Encodes a race as an animated WebP.
+pub fn race_to_webp(
+    graphics: &mut SoftwareGraphics,
+    course: &Course,
+    tracker: &Tracker,
+    frames_per_round: usize,
+    frame_delay_ms: u16,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let params = webp_animator::Params {
+        width: graphics.draw.image.width,
+        height: graphics.draw.image.height,
+        background_bgra: [0xFF; 4],
+        loop_count: 0,
+        has_alpha: true,
+    };
+    let mut encoder = webp_animator::WebPAnimator::new(params)?;
+    let mut frame_buf = Vec::new();
+    render_race(graphics, course, tracker, frames_per_round, |img| {
+        frame_buf.clear();
+        let frame_enc = image_webp::WebPEncoder::new(&mut frame_buf);
+        frame_enc.encode(
+            &img.data,
+            img.width,
+            img.height,
+            image_webp::ColorType::Rgba8,
+        )?;
+        encoder.add_webp_image(&frame_buf, None, frame_delay_ms.into())?;
+        Ok(())
+    })?;
+    let mut out = Vec::new();
+    encoder.write(&mut out)?;
+    Ok(out)
+}
@@ -0,0 +1,68 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{course::Course, level::LevelData, tracker::Tracker};
+
+pub static REPLAY_FILE_NAME: &str = "race.racereplay";
+static REPLAY_VERSION: u32 = 1;
+
+/// A recorded race: the level, the course, the seed used for random spawns
+/// and every round of the race.  This is the contents of a `.racereplay`
+/// file.
+#[derive(Deserialize)]
+pub struct ReplayFile {
+    pub version: u32,
+    pub level: LevelData,
+    pub course: Course,
+    pub seed: u64,
+    pub tracker: Tracker,
+}
+
+#[derive(Serialize)]
+struct ReplayFileRef<'a> {
+    version: u32,
+    level: &'a LevelData,
+    course: &'a Course,
+    seed: u64,
+    tracker: &'a Tracker,
+}
+
+impl ReplayFile {
+    /// Encodes a race without copying its round history.
+    pub fn encode(
+        level: &LevelData,
+        course: &Course,
+        seed: u64,
+        tracker: &Tracker,
+    ) -> Result<Vec<u8>, anyhow::Error> {
+        let replay = ReplayFileRef {
+            version: REPLAY_VERSION,
+            level,
+            course,
+            seed,
+            tracker,
+        };
+        Ok(rmp_serde::to_vec_named(&replay)?)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, anyhow::Error> {
+        let replay: Self = rmp_serde::from_slice(data)?;
+        if replay.version > REPLAY_VERSION {
+            anyhow::bail!("replay was written by a newer version of the game");
+        }
+        replay.validate()?;
+        Ok(replay)
+    }
+
+    /// Checks that the race only has cars that are in the level, so that a
+    /// damaged file can't crash the viewer.
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        self.level.validate().map_err(anyhow::Error::msg)?;
+        let cars = self.level.cars.min(self.tracker.get_crashes().len());
+        let ids = self.tracker.get_cars().iter().flatten().map(|car| car.id);
+        let finished = self.tracker.get_finishes().iter().copied();
+        if let Some(id) = ids.chain(finished).find(|&id| id >= cars) {
+            anyhow::bail!("the replay has car {id}, but the level only has {cars} cars");
+        }
+        Ok(())
+    }
+}
@@ -9,9 +9,13 @@ use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 use crate::{
     course::{Course, TileCoord},
+    history::UndoTree,
     level::{LevelData, SolveData},
+    metrics::Metric,
+    pack::load_user_packs,
+    records::{ParetoEntry, RunRecord, add_to_pareto_front, best_entry},
     tile::Tile,
-    ui::loader::load_levels,
+    ui::{loader::load_levels, settings::Settings},
 };
 
 fn hash_for<T: Hash>(data: &T) -> u64 {
@@ -20,6 +24,18 @@ fn hash_for<T: Hash>(data: &T) -> u64 {
     hasher.finish()
 }
 
+/// The hash that levels were stored under before the banned tiles were part
+/// of it.
+fn legacy_hash(lvl: &LevelData) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    lvl.cars.hash(&mut hasher);
+    lvl.finish.hash(&mut hasher);
+    if let Some(m) = lvl.max_rounds {
+        m.hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
 fn make_pkv() -> PkvStore {
     PkvStore::new("dgulotta", "race-game")
 }
@@ -39,6 +55,13 @@ pub fn save_or_log_err<T: Serialize>(key: &str, value: &T, err_msg: &str) {
     }
 }
 
+pub fn remove_or_log_err(key: &str, err_msg: &str) {
+    let mut pkv = make_pkv();
+    if let Err(e) = pkv.remove(key) {
+        error!("{err_msg}: {e}");
+    }
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 pub fn load<T: DeserializeOwned>(pkv: &PkvStore, key: &str) -> Result<Option<T>, String> {
     match pkv.get(key) {
@@ -68,14 +91,127 @@ pub fn save<T: Serialize>(pkv: &mut PkvStore, key: &str, value: &T) -> Result<()
 #[cfg(target_arch = "wasm32")]
 pub fn save<T: Serialize>(pkv: &mut PkvStore, key: &str, value: &T) -> Result<(), String> {
     use base64::engine::{general_purpose::STANDARD, Engine};
-    let data = rmp_serde::encode::to_vec(value).map_err(|err| err.to_string())?;
+    let data = rmp_serde::encode::to_vec_named(value).map_err(|err| err.to_string())?;
     let data_str = STANDARD.encode(data);
     pkv.set(key, &data_str).map_err(|err| err.to_string())
 }
 
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SaveSlot {
+    pub name: String,
+    pub id: usize,
+    pub solve: Option<SolveData>,
+}
+
+/// The named versions of the course that have been saved for a level.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SlotIndex {
+    pub slots: Vec<SaveSlot>,
+    pub active: usize,
+    next_id: usize,
+}
+
+impl Default for SlotIndex {
+    fn default() -> Self {
+        Self {
+            slots: vec![SaveSlot {
+                name: "Main".to_string(),
+                id: 0,
+                solve: None,
+            }],
+            active: 0,
+            next_id: 1,
+        }
+    }
+}
+
+impl SlotIndex {
+    pub fn active_slot(&self) -> &SaveSlot {
+        &self.slots[self.active]
+    }
+
+    pub fn add(&mut self, name: String) -> usize {
+        self.slots.push(SaveSlot {
+            name,
+            id: self.next_id,
+            solve: None,
+        });
+        self.next_id += 1;
+        self.slots.len() - 1
+    }
+
+    pub fn remove(&mut self, n: usize) {
+        if self.slots.len() > 1 {
+            self.slots.remove(n);
+            if self.active >= n && self.active > 0 {
+                self.active -= 1;
+            }
+        }
+    }
+
+    pub fn best(&self) -> Option<usize> {
+        let mut best: Option<(usize, SolveData)> = None;
+        for (n, slot) in self.slots.iter().enumerate() {
+            if let Some(s) = slot.solve
+                && best.is_none_or(|(_, b)| s.is_better_than(&b))
+            {
+                best = Some((n, s));
+            }
+        }
+        best.map(|(n, _)| n)
+    }
+}
+
+fn slot_key(lvl: &LevelData, id: usize) -> String {
+    if id == 0 {
+        format!("track/{}", hash_for(lvl))
+    } else {
+        format!("track/{}/{id}", hash_for(lvl))
+    }
+}
+
+pub fn load_slots(lvl: &LevelData) -> SlotIndex {
+    let key = format!("slots/{}", hash_for(lvl));
+    load_or_log_err(&key, "Failed to load save slots").unwrap_or_default()
+}
+
+pub fn save_slots(lvl: &LevelData, slots: &SlotIndex) {
+    let key = format!("slots/{}", hash_for(lvl));
+    save_or_log_err(&key, slots, "Failed to save save slots")
+}
+
+pub fn save_course_slot(lvl: &LevelData, id: usize, course: &Course) {
+    save_or_log_err(&slot_key(lvl, id), course, "Failed to save course")
+}
+
+pub fn load_course_slot(lvl: &LevelData, id: usize) -> Option<Course> {
+    load_or_log_err(&slot_key(lvl, id), "Failed to load course")
+}
+
+fn history_key(lvl: &LevelData, id: usize) -> String {
+    format!("undo/{}/{id}", hash_for(lvl))
+}
+
+pub fn save_history(lvl: &LevelData, id: usize, history: &UndoTree) {
+    save_or_log_err(
+        &history_key(lvl, id),
+        history,
+        "Failed to save undo history",
+    )
+}
+
+pub fn load_history(lvl: &LevelData, id: usize) -> Option<UndoTree> {
+    load_or_log_err(&history_key(lvl, id), "Failed to load undo history")
+}
+
+/// Deletes the course and undo history of a save slot that was removed.
+pub fn remove_course_slot(lvl: &LevelData, id: usize) {
+    remove_or_log_err(&slot_key(lvl, id), "Failed to delete course");
+    remove_or_log_err(&history_key(lvl, id), "Failed to delete undo history");
+}
+
 pub fn save_course(lvl: &LevelData, course: &Course) {
-    let key = format!("track/{}", hash_for(lvl));
-    save_or_log_err(&key, course, "Failed to save course")
+    save_course_slot(lvl, load_slots(lvl).active_slot().id, course)
 }
 
 /*
@@ -87,20 +223,67 @@ pub fn have_saved_course(lvl: &LevelData) -> bool {
 */
 
 pub fn load_course(lvl: &LevelData) -> Option<Course> {
-    let key = format!("track/{}", hash_for(lvl));
-    load_or_log_err(&key, "Failed to load course")
-    //let data: Vec<(TileCoord, Tile)> = load_or_log_err(&key, "Failed to load course")?;
-    //Some(data.into_iter().collect())
+    load_course_slot(lvl, load_slots(lvl).active_slot().id)
 }
 
 pub fn course_is_nonempty(lvl: &LevelData) -> bool {
     load_course(lvl).is_some_and(|c| !c.is_empty())
 }
 
-pub fn save_solve(lvl: &LevelData, solve: &SolveData) {
-    let key = format!("solve/{}", hash_for(lvl));
-    let best = solve.combine_option(&load_or_log_err(&key, "Failed to load solve data"));
-    save_or_log_err(&key, &best, "Failed to save solve data");
+pub fn load_pareto(lvl: &LevelData) -> Vec<ParetoEntry> {
+    let key = format!("pareto/{}", hash_for(lvl));
+    load_or_log_err(&key, "Failed to load best solutions").unwrap_or_default()
+}
+
+fn save_pareto(lvl: &LevelData, front: &[ParetoEntry]) {
+    let key = format!("pareto/{}", hash_for(lvl));
+    save_or_log_err(&key, &front, "Failed to save best solutions");
+}
+
+/// Adds a course to the best courses of the level and returns them.
+fn add_pareto(lvl: &LevelData, solve: &SolveData, course: &Course) -> Vec<ParetoEntry> {
+    let mut front = load_pareto(lvl);
+    let entry = ParetoEntry {
+        solve: *solve,
+        course: course.clone(),
+    };
+    if add_to_pareto_front(&mut front, entry) {
+        save_pareto(lvl, &front);
+    }
+    front
+}
+
+pub fn save_solve(lvl: &LevelData, solve: &SolveData, course: &Course) {
+    let mut slots = load_slots(lvl);
+    let active = slots.active;
+    let slot = &mut slots.slots[active];
+    if slot.solve.is_none_or(|s| solve.is_better_than(&s)) {
+        slot.solve = Some(*solve);
+    }
+    save_slots(lvl, &slots);
+    add_run(lvl, solve);
+    let front = add_pareto(lvl, solve, course);
+    if let Some(best) = best_entry(&front, Metric::Tiles) {
+        replace_solve(lvl, &best.solve);
+    }
+}
+
+static MAX_RUNS: usize = 500;
+
+fn add_run(lvl: &LevelData, solve: &SolveData) {
+    let mut runs = load_runs(lvl);
+    runs.push(RunRecord::now(*solve));
+    if runs.len() > MAX_RUNS {
+        runs.drain(..runs.len() - MAX_RUNS);
+    }
+    let key = format!("runs/{}", hash_for(lvl));
+    save_or_log_err(&key, &runs, "Failed to save run history");
+}
+
+/// Returns every successful run of the level, oldest first.
+pub fn load_runs(lvl: &LevelData) -> Vec<RunRecord> {
+    let key = format!("runs/{}", hash_for(lvl));
+    load_or_log_err(&key, "Failed to load run history").unwrap_or_default()
 }
 
 pub fn load_solve(lvl: &LevelData) -> Option<SolveData> {
@@ -108,6 +291,77 @@ pub fn load_solve(lvl: &LevelData) -> Option<SolveData> {
     load_or_log_err(&key, "Failed to load solve data")
 }
 
+fn replace_solve(lvl: &LevelData, solve: &SolveData) {
+    let key = format!("solve/{}", hash_for(lvl));
+    save_or_log_err(&key, solve, "Failed to save solve data");
+}
+
+pub fn load_custom_levels() -> Vec<LevelData> {
+    load_or_log_err("custom_levels", "Failed to load custom levels").unwrap_or_default()
+}
+
+pub fn save_custom_levels(levels: &[LevelData]) {
+    save_or_log_err("custom_levels", &levels, "Failed to save custom levels");
+}
+
+pub fn add_custom_level(lvl: &LevelData) {
+    let mut levels = load_custom_levels();
+    let hash = hash_for(lvl);
+    if !levels.iter().any(|l| hash_for(l) == hash) {
+        levels.push(lvl.clone());
+        save_custom_levels(&levels);
+    }
+}
+
+/// Adds a level to the custom levels unless it is a built-in level, a level
+/// in an installed pack or already a custom level.
+pub fn add_unknown_level(lvl: &LevelData) {
+    let hash = hash_for(lvl);
+    if !stored_levels().iter().any(|l| hash_for(l) == hash) {
+        add_custom_level(lvl);
+    }
+}
+
+pub fn rename_custom_level(n: usize, name: &str) {
+    let mut levels = load_custom_levels();
+    if let Some(lvl) = levels.get_mut(n) {
+        lvl.name = name.to_string();
+        save_custom_levels(&levels);
+    }
+}
+
+/// Deletes a custom level together with its courses, undo histories, solve
+/// and records, unless another level shares them.
+pub fn remove_custom_level(n: usize) {
+    let mut levels = load_custom_levels();
+    if n < levels.len() {
+        let lvl = levels.remove(n);
+        save_custom_levels(&levels);
+        if !stored_levels().iter().any(|l| l.same_rules(&lvl)) {
+            remove_level_data(&lvl);
+        }
+    }
+}
+
+fn remove_level_data(lvl: &LevelData) {
+    for slot in &load_slots(lvl).slots {
+        remove_course_slot(lvl, slot.id);
+    }
+    let hash = hash_for(lvl);
+    remove_or_log_err(&format!("slots/{hash}"), "Failed to delete save slots");
+    remove_or_log_err(&format!("solve/{hash}"), "Failed to delete solve");
+    remove_or_log_err(&format!("runs/{hash}"), "Failed to delete runs");
+    remove_or_log_err(&format!("pareto/{hash}"), "Failed to delete best solutions");
+}
+
+pub fn load_current_pack() -> Option<String> {
+    load_or_log_err("current_pack", "Failed to load selected level pack")
+}
+
+pub fn save_current_pack(name: &str) {
+    save_or_log_err("current_pack", &name, "Failed to save selected level pack");
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct TileData {
     pub coord: TileCoord,
@@ -146,6 +400,29 @@ pub fn saved_courses_to_toml() -> String {
     toml::to_string(&data).unwrap()
 }
 
+pub fn course_to_toml(name: &str, course: &Course) -> String {
+    let mut data = BTreeMap::new();
+    data.insert(name.to_string(), course_to_vec(course));
+    toml::to_string(&data).unwrap()
+}
+
+/// Reads a course from a file in the format written by [`course_to_toml`].
+/// If the file holds several courses, the one named `name` is used.
+pub fn course_from_toml(name: &str, data: &str) -> Result<Course, anyhow::Error> {
+    let mut courses = courses_from_toml(data)?;
+    if let Some(course) = courses.remove(name) {
+        Ok(course)
+    } else if courses.len() == 1 {
+        Ok(courses.into_values().next().unwrap())
+    } else if courses.is_empty() {
+        Err(anyhow::anyhow!("the file does not contain a course"))
+    } else {
+        Err(anyhow::anyhow!(
+            "the file does not contain a course named \"{name}\""
+        ))
+    }
+}
+
 pub fn courses_from_toml(data: &str) -> Result<HashMap<String, Course>, toml::de::Error> {
     let data: HashMap<String, Vec<TileData>> = toml::from_str(data)?;
     let all = data
@@ -157,3 +434,292 @@ pub fn courses_from_toml(data: &str) -> Result<HashMap<String, Course>, toml::de
         .collect();
     Ok(all)
 }
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ImportPolicy {
+    KeepBest,
+    Overwrite,
+    Skip,
+}
+
+impl ImportPolicy {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::KeepBest => "Keep best",
+            Self::Overwrite => "Overwrite",
+            Self::Skip => "Skip existing",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ProfileLevel {
+    pub level: LevelData,
+    pub slots: SlotIndex,
+    /// The course in each save slot, keyed by the id of the slot.
+    pub courses: BTreeMap<usize, Course>,
+    pub pareto: Vec<ParetoEntry>,
+    pub solve: Option<SolveData>,
+}
+
+const STORAGE_VERSION: u32 = 1;
+
+/// Every level that can have data in storage: the built-in levels, the levels
+/// in installed packs and the custom levels.
+fn stored_levels() -> Vec<LevelData> {
+    let pack_levels = load_user_packs()
+        .into_iter()
+        .flatten()
+        .flat_map(|pack| pack.levels);
+    load_levels()
+        .into_iter()
+        .chain(pack_levels)
+        .map(|lvl| lvl.as_ref().clone())
+        .chain(load_custom_levels())
+        .collect()
+}
+
+fn copy_key<T: Serialize + DeserializeOwned>(
+    pkv: &mut PkvStore,
+    from: &str,
+    to: &str,
+    copied: &mut Vec<String>,
+) {
+    match load::<T>(pkv, from) {
+        Ok(Some(value)) => {
+            if let Err(e) = save(pkv, to, &value) {
+                error!("Failed to migrate {from}: {e}");
+            }
+            copied.push(from.to_string());
+        }
+        Ok(None) => (),
+        Err(e) => error!("Failed to migrate {from}: {e}"),
+    }
+}
+
+/// Moves the data of every level from the keys that it was stored under by
+/// older versions of the game.
+pub fn migrate_storage() {
+    let mut pkv = make_pkv();
+    let version: u32 = load(&pkv, "storage_version").ok().flatten().unwrap_or(0);
+    if version >= STORAGE_VERSION {
+        return;
+    }
+    // The old keys are only removed at the end, since levels that differ
+    // only in their banned tiles used to share them.
+    let mut copied = Vec::new();
+    for lvl in stored_levels() {
+        let (old, new) = (legacy_hash(&lvl), hash_for(&lvl));
+        if old == new {
+            continue;
+        }
+        let slots: SlotIndex = load(&pkv, &format!("slots/{old}"))
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+        for slot in &slots.slots {
+            let id = slot.id;
+            let track = if id == 0 {
+                String::new()
+            } else {
+                format!("/{id}")
+            };
+            let (from, to) = (format!("track/{old}{track}"), format!("track/{new}{track}"));
+            copy_key::<Course>(&mut pkv, &from, &to, &mut copied);
+            let (from, to) = (format!("undo/{old}/{id}"), format!("undo/{new}/{id}"));
+            copy_key::<UndoTree>(&mut pkv, &from, &to, &mut copied);
+        }
+        let (from, to) = (format!("slots/{old}"), format!("slots/{new}"));
+        copy_key::<SlotIndex>(&mut pkv, &from, &to, &mut copied);
+        let (from, to) = (format!("solve/{old}"), format!("solve/{new}"));
+        copy_key::<SolveData>(&mut pkv, &from, &to, &mut copied);
+        let (from, to) = (format!("runs/{old}"), format!("runs/{new}"));
+        copy_key::<Vec<RunRecord>>(&mut pkv, &from, &to, &mut copied);
+        let (from, to) = (format!("pareto/{old}"), format!("pareto/{new}"));
+        copy_key::<Vec<ParetoEntry>>(&mut pkv, &from, &to, &mut copied);
+    }
+    for key in copied {
+        if let Err(e) = pkv.remove(&key) {
+            error!("Failed to remove {key}: {e}");
+        }
+    }
+    if let Err(e) = save(&mut pkv, "storage_version", &STORAGE_VERSION) {
+        error!("Failed to save storage version: {e}");
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Profile {
+    pub version: u32,
+    pub levels: Vec<ProfileLevel>,
+    pub custom_levels: Vec<LevelData>,
+    pub settings: Settings,
+}
+
+const PROFILE_VERSION: u32 = 1;
+
+impl Profile {
+    pub fn collect(settings: &Settings) -> Self {
+        let custom_levels = load_custom_levels();
+        let levels = stored_levels()
+            .into_iter()
+            .filter_map(|lvl| {
+                let slots = load_slots(&lvl);
+                let courses: BTreeMap<_, _> = slots
+                    .slots
+                    .iter()
+                    .filter_map(|slot| Some((slot.id, load_course_slot(&lvl, slot.id)?)))
+                    .collect();
+                let pareto = load_pareto(&lvl);
+                let solve = load_solve(&lvl);
+                if courses.is_empty() && pareto.is_empty() && solve.is_none() {
+                    None
+                } else {
+                    Some(ProfileLevel {
+                        level: lvl,
+                        slots,
+                        courses,
+                        pareto,
+                        solve,
+                    })
+                }
+            })
+            .collect();
+        Self {
+            version: PROFILE_VERSION,
+            levels,
+            custom_levels,
+            settings: settings.clone(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, anyhow::Error> {
+        Ok(rmp_serde::to_vec_named(self)?)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, anyhow::Error> {
+        let profile: Self = rmp_serde::from_slice(data)?;
+        if profile.version > PROFILE_VERSION {
+            anyhow::bail!("profile was written by a newer version of the game");
+        }
+        Ok(profile)
+    }
+
+    /// Writes the courses, solves and custom levels in this profile to
+    /// storage.  Returns the number of levels whose data was changed.
+    pub fn apply(&self, policy: ImportPolicy) -> usize {
+        let mut custom = load_custom_levels();
+        for lvl in &self.custom_levels {
+            let hash = hash_for(lvl);
+            match custom.iter().position(|l| hash_for(l) == hash) {
+                Some(n) if policy == ImportPolicy::Overwrite => custom[n] = lvl.clone(),
+                Some(_) => (),
+                None => custom.push(lvl.clone()),
+            }
+        }
+        save_custom_levels(&custom);
+        self.levels
+            .iter()
+            .filter(|entry| import_level(entry, policy))
+            .count()
+    }
+}
+
+/// Decides whether an imported course replaces the local one and which
+/// solve is kept, given the imported and local solves and whether there is
+/// a local course.
+pub fn import_choice(
+    policy: ImportPolicy,
+    imported: Option<SolveData>,
+    local: Option<SolveData>,
+    have_course: bool,
+) -> (bool, Option<SolveData>) {
+    match policy {
+        ImportPolicy::Overwrite => (true, imported.or(local)),
+        ImportPolicy::Skip => (!have_course, local.or(imported)),
+        ImportPolicy::KeepBest => {
+            let better = match (imported, local) {
+                (Some(new), Some(old)) => new.is_better_than(&old),
+                (Some(_), None) => true,
+                (None, _) => false,
+            };
+            let solve = if better { imported } else { local };
+            (better || !have_course, solve)
+        }
+    }
+}
+
+/// Merges the imported save slots into the local ones.  Slots are matched
+/// by name, and imported slots without a local counterpart are added.
+fn import_slots(entry: &ProfileLevel, policy: ImportPolicy) -> bool {
+    let lvl = &entry.level;
+    let mut slots = load_slots(lvl);
+    let mut changed = false;
+    for slot in &entry.slots.slots {
+        let course = entry.courses.get(&slot.id);
+        let n = match slots.slots.iter().position(|s| s.name == slot.name) {
+            Some(n) => n,
+            None if course.is_some() || slot.solve.is_some() => {
+                changed = true;
+                slots.add(slot.name.clone())
+            }
+            None => continue,
+        };
+        let local = &mut slots.slots[n];
+        let have_course = load_course_slot(lvl, local.id).is_some_and(|c| !c.is_empty());
+        let (take_course, solve) = import_choice(policy, slot.solve, local.solve, have_course);
+        if let Some(course) = course
+            && take_course
+        {
+            save_course_slot(lvl, local.id, course);
+            changed = true;
+        }
+        if solve != local.solve {
+            local.solve = solve;
+            changed = true;
+        }
+    }
+    if policy == ImportPolicy::Overwrite
+        && let Some(active) = entry.slots.slots.get(entry.slots.active)
+        && let Some(n) = slots.slots.iter().position(|s| s.name == active.name)
+        && n != slots.active
+    {
+        slots.active = n;
+        changed = true;
+    }
+    if changed {
+        save_slots(lvl, &slots);
+    }
+    changed
+}
+
+/// Imports the save slots and best courses of a level.  The best courses
+/// record what was achieved rather than what is being worked on, so they are
+/// merged under every policy, and the solve of the level is taken from them.
+/// Only levels solved before best courses were recorded use the solve in the
+/// profile.
+fn import_level(entry: &ProfileLevel, policy: ImportPolicy) -> bool {
+    let lvl = &entry.level;
+    let mut changed = import_slots(entry, policy);
+    let mut front = load_pareto(lvl);
+    let mut front_changed = false;
+    for e in &entry.pareto {
+        front_changed |= add_to_pareto_front(&mut front, e.clone());
+    }
+    if front_changed {
+        save_pareto(lvl, &front);
+        changed = true;
+    }
+    let local_solve = load_solve(lvl);
+    let solve = match best_entry(&front, Metric::Tiles) {
+        Some(best) => Some(best.solve),
+        None => import_choice(policy, entry.solve, local_solve, true).1,
+    };
+    if let Some(s) = solve
+        && solve != local_solve
+    {
+        replace_solve(lvl, &s);
+        changed = true;
+    }
+    changed
+}
@@ -0,0 +1,192 @@
+use anyhow::{anyhow, bail};
+use base64::engine::{Engine, general_purpose::URL_SAFE_NO_PAD};
+use enum_map::EnumMap;
+use strum::IntoEnumIterator;
+
+use crate::{
+    course::{Course, TileCoord},
+    direction::DihedralElement,
+    level::LevelData,
+    tile::{Tile, TileType},
+};
+
+static SHARE_PREFIX: &str = "race:";
+static SHARE_VERSION: u8 = 2;
+static MAX_DECODED_SIZE: usize = 1 << 20;
+
+/// A course together with the level it was built for, as read from a share
+/// code.
+pub struct SharedCourse {
+    pub level: LevelData,
+    pub course: Course,
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn byte(&mut self, b: u8) {
+        self.0.push(b);
+    }
+
+    fn uint(&mut self, mut n: usize) {
+        while n >= 0x80 {
+            self.0.push((n as u8) | 0x80);
+            n >>= 7;
+        }
+        self.0.push(n as u8);
+    }
+
+    fn int(&mut self, n: isize) {
+        self.uint(((n << 1) ^ (n >> (isize::BITS - 1))) as usize);
+    }
+
+    fn str(&mut self, s: &str) {
+        self.uint(s.len());
+        self.0.extend_from_slice(s.as_bytes());
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, anyhow::Error> {
+        let (&b, rest) = self.0.split_first().ok_or_else(truncated)?;
+        self.0 = rest;
+        Ok(b)
+    }
+
+    fn uint(&mut self) -> Result<usize, anyhow::Error> {
+        let mut n = 0;
+        for shift in (0..usize::BITS).step_by(7) {
+            let b = self.byte()?;
+            n |= ((b & 0x7F) as usize) << shift;
+            if b & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        bail!("share code contains an invalid number")
+    }
+
+    fn int(&mut self) -> Result<isize, anyhow::Error> {
+        let n = self.uint()?;
+        Ok(((n >> 1) as isize) ^ -((n & 1) as isize))
+    }
+
+    fn str(&mut self) -> Result<String, anyhow::Error> {
+        let len = self.uint()?;
+        if len > self.0.len() {
+            return Err(truncated());
+        }
+        let (s, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(String::from_utf8(s.to_vec())?)
+    }
+}
+
+fn truncated() -> anyhow::Error {
+    anyhow!("share code is truncated")
+}
+
+fn pack_tile(tile: &Tile) -> u8 {
+    (tile.tile_type as u8) | ((tile.transform as u8) << 3) | ((tile.offset & 1) << 6)
+}
+
+fn unpack_tile(b: u8) -> Result<Tile, anyhow::Error> {
+    let tile_type = TileType::from_repr((b & 7) as usize).ok_or_else(|| anyhow!("bad tile"))?;
+    let transform =
+        DihedralElement::from_repr(((b >> 3) & 7) as usize).ok_or_else(|| anyhow!("bad tile"))?;
+    Ok(Tile {
+        tile_type,
+        transform,
+        offset: (b >> 6) & 1,
+    })
+}
+
+fn pack(lvl: &LevelData, course: &Course) -> Vec<u8> {
+    let mut w = Writer(Vec::new());
+    w.byte(SHARE_VERSION);
+    w.str(&lvl.name);
+    w.uint(lvl.cars);
+    w.uint(lvl.finish.len());
+    for &n in &lvl.finish {
+        w.uint(n);
+    }
+    let banned = TileType::iter()
+        .filter(|&t| lvl.banned[t])
+        .fold(0, |acc, t| acc | (1 << (t as u8)));
+    w.byte(banned);
+    w.uint(lvl.max_rounds.unwrap_or(0));
+    let mut tiles: Vec<_> = course.iter().collect();
+    tiles.sort_by_key(|(pos, _)| (pos.1, pos.0));
+    w.uint(tiles.len());
+    let mut last = TileCoord(0, 0);
+    for (&pos, tile) in tiles {
+        let delta = pos - last;
+        w.int(delta.0);
+        w.int(delta.1);
+        w.byte(pack_tile(tile));
+        last = pos;
+    }
+    w.0
+}
+
+fn unpack(data: &[u8]) -> Result<SharedCourse, anyhow::Error> {
+    let mut r = Reader(data);
+    let version = r.byte()?;
+    if version == 0 || version > SHARE_VERSION {
+        bail!("share code has unsupported version {version}");
+    }
+    let name = r.str()?;
+    let cars = r.uint()?;
+    let finish_len = r.uint()?;
+    let finish = (0..finish_len)
+        .map(|_| r.uint())
+        .collect::<Result<Vec<_>, _>>()?;
+    let banned_bits = r.byte()?;
+    let banned = EnumMap::from_fn(|t: TileType| banned_bits & (1 << (t as u8)) != 0);
+    let max_rounds = match version {
+        1 => None,
+        _ => Some(r.uint()?).filter(|&n| n > 0),
+    };
+    let num_tiles = r.uint()?;
+    let mut course = Course::new_with_hasher_and_ptr_kind(Default::default());
+    let mut pos = TileCoord(0, 0);
+    for _ in 0..num_tiles {
+        let (dx, dy) = (r.int()?, r.int()?);
+        pos = match (pos.0.checked_add(dx), pos.1.checked_add(dy)) {
+            (Some(x), Some(y)) => TileCoord(x, y),
+            _ => bail!("share code has a tile out of range"),
+        };
+        course.insert_mut(pos, unpack_tile(r.byte()?)?);
+    }
+    if !r.0.is_empty() {
+        bail!("share code has extra data after the course");
+    }
+    let level = LevelData {
+        name,
+        cars,
+        finish,
+        tutorial: None,
+        banned,
+        tutorial_text: None,
+        max_rounds,
+        par: None,
+    };
+    Ok(SharedCourse { level, course })
+}
+
+pub fn encode(lvl: &LevelData, course: &Course) -> String {
+    let compressed = miniz_oxide::deflate::compress_to_vec(&pack(lvl, course), 10);
+    format!("{SHARE_PREFIX}{}", URL_SAFE_NO_PAD.encode(compressed))
+}
+
+pub fn decode(code: &str) -> Result<SharedCourse, anyhow::Error> {
+    let code: String = code.split_whitespace().collect();
+    let body = code.strip_prefix(SHARE_PREFIX).unwrap_or(&code);
+    let compressed = URL_SAFE_NO_PAD.decode(body)?;
+    let data = miniz_oxide::inflate::decompress_to_vec_with_limit(&compressed, MAX_DECODED_SIZE)
+        .map_err(|_| anyhow!("share code is corrupted"))?;
+    let shared = unpack(&data)?;
+    shared.level.validate().map_err(anyhow::Error::msg)?;
+    Ok(shared)
+}
@@ -1,4 +1,5 @@
-use notan::random::rand::{Rng, thread_rng};
+use notan::random::rand::{Rng, SeedableRng, rngs::StdRng, thread_rng};
+use serde::{Deserialize, Serialize};
 
 use crate::course::{Course, TileCoord};
 use crate::direction::Direction;
@@ -10,7 +11,7 @@ use hashbrown::HashMap;
 
 static MAX_ROUNDS: usize = 1000;
 
-#[derive(PartialEq, Eq, Clone, Copy, Hash, Debug)]
+#[derive(PartialEq, Eq, Clone, Copy, Hash, Debug, Serialize, Deserialize)]
 pub struct CarCoord(pub isize, pub isize);
 
 impl Add<Direction> for CarCoord {
@@ -63,7 +64,7 @@ pub enum SpawnPolicy {
     Random(u8),
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct CarData {
     pub id: usize,
     pub pos: CarCoord,
@@ -71,7 +72,9 @@ pub struct CarData {
 }
 
 impl CarData {
-    fn tile_pos(&self) -> CarCoord {
+    /// The position of the center of the tile that the car is in or is
+    /// waiting to enter.
+    pub fn tile_pos(&self) -> CarCoord {
         self.pos + self.dir
     }
 }
@@ -102,15 +105,13 @@ pub struct Simulator {
     round: usize,
     cars: Vec<CarData>,
     spawn_policy: SpawnPolicy,
+    seed: u64,
+    rng: StdRng,
     next_car: usize,
     max_cars: usize,
     stream: Vec<SimEvent>,
 }
 
-fn random_8(n: u8) -> bool {
-    thread_rng().gen_ratio(n as u32, 8)
-}
-
 pub const STRAIGHT_ENTRANCE: Direction = Direction::Up;
 pub const STRAIGHT_EXIT: Direction = Direction::Up;
 pub const TURN_ENTRANCE: Direction = Direction::Up;
@@ -244,15 +245,16 @@ impl<'a> RoundRunner<'a> {
             .is_none_or(|&i| self.status[i] == MoveStatus::Moving)
     }
 
-    fn check_add_car(&self) -> bool {
+    fn check_add_car(&mut self) -> bool {
         match self.sim.spawn_policy {
             SpawnPolicy::Always => true,
-            SpawnPolicy::Random(n) => random_8(n),
+            SpawnPolicy::Random(n) => self.sim.rng.gen_ratio(n as u32, 8),
         }
     }
 
     fn add_cars(&mut self) {
-        for start in self.sim.starts.iter() {
+        for n in 0..self.sim.starts.len() {
+            let start = self.sim.starts[n];
             if self.sim.next_car < self.sim.max_cars
                 && self.is_spot_free(start.0)
                 && self.check_add_car()
@@ -285,6 +287,11 @@ impl<'a> RoundRunner<'a> {
 
 impl Simulator {
     pub fn new(course: Course, max_cars: usize) -> Self {
+        Self::with_seed(course, max_cars, thread_rng().r#gen())
+    }
+
+    /// Creates a simulator whose random spawns are determined by `seed`.
+    pub fn with_seed(course: Course, max_cars: usize, seed: u64) -> Self {
         let starts: Vec<_> = course
             .iter()
             .filter_map(|(pos, tile)| {
@@ -302,6 +309,8 @@ impl Simulator {
             round: 0,
             cars: Vec::new(),
             spawn_policy: SpawnPolicy::Always,
+            seed,
+            rng: StdRng::seed_from_u64(seed),
             next_car: 0,
             max_cars,
             stream: Vec::new(),
@@ -316,6 +325,10 @@ impl Simulator {
         self.spawn_policy = p;
     }
 
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
     fn out_dir(&self, tile: &Tile, in_dir: Direction) -> Direction {
         let offset = ((self.round as u8) ^ tile.offset) & 1 != 0;
         match tile.tile_type {
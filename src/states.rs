@@ -1,22 +1,38 @@
-use regex::Regex;
-use std::{rc::Rc, sync::LazyLock, time::Duration};
+use enum_map::EnumMap;
+use hashbrown::HashMap;
+use std::{rc::Rc, time::Duration};
 
 use notan::math::Vec2;
 
 use crate::{
     course::{Course, CourseEdit, TileCoord, course_center},
     direction::{DihedralElement, Direction},
+    finish::parse_finish,
+    ghost::GhostRace,
     input::Action,
     level::{LevelData, SolveData},
+    metrics::{Metric, measure},
+    pack::{LevelPack, MAIN_PACK, PackMeta, load_user_packs},
     path::Path,
-    playback::{CarAnimation, Playback, animations},
-    save::{load_course, load_solve, save_course, save_solve},
+    playback::{Breakpoint, Player},
+    records::{ParetoEntry, RunRecord, best_entry},
+    replay_file::ReplayFile,
+    save::{
+        ImportPolicy, SlotIndex, load_current_pack, load_custom_levels, load_pareto, load_runs,
+        load_solve, remove_custom_level, rename_custom_level, save_course_slot, save_current_pack,
+        save_solve,
+    },
+    share::SharedCourse,
     simulator::{CarData, Simulator},
     tile::{Tile, TileType},
     tooltip::TooltipState,
     tracker::Tracker,
+    traffic::{CarTimeline, Heatmap, TileTraffic, car_timelines, first_divergence, tile_traffic},
     ui::{
+        course_image::CourseImageOptions,
         export::{FileExport, make_exporter},
+        import::{FileImport, make_importer},
+        replay::ReplayOptions,
         screen::Screen,
     },
 };
@@ -68,13 +84,70 @@ impl Default for TrackSelection {
     }
 }
 
-pub struct SelectState {
+pub struct PackProgress {
+    pub pack: LevelPack,
     pub solved: Vec<Option<SolveData>>,
+    pareto: Vec<Vec<ParetoEntry>>,
+}
+
+impl PackProgress {
+    pub fn new(pack: LevelPack) -> Self {
+        let solved = pack.levels.iter().map(|lvl| load_solve(lvl)).collect();
+        let pareto = pack.levels.iter().map(|lvl| load_pareto(lvl)).collect();
+        Self {
+            pack,
+            solved,
+            pareto,
+        }
+    }
+
+    /// The solve of the course for level `n` that is best in `metric`.
+    /// Falls back to the stored solve for levels solved before best courses
+    /// were recorded, which may take the best of each metric from different
+    /// courses.
+    pub fn best_solve(&self, n: usize, metric: Metric) -> Option<SolveData> {
+        best_entry(&self.pareto[n], metric)
+            .map(|e| e.solve)
+            .or(self.solved[n])
+    }
+
+    pub fn num_solved(&self) -> usize {
+        self.solved.iter().filter(|s| s.is_some()).count()
+    }
+}
+
+fn custom_level_progress() -> PackProgress {
+    PackProgress::new(LevelPack {
+        meta: Default::default(),
+        levels: load_custom_levels().into_iter().map(Rc::new).collect(),
+    })
+}
+
+pub struct LevelRecords {
+    pub level: Rc<LevelData>,
+    pub runs: Vec<RunRecord>,
+    pub pareto: Vec<ParetoEntry>,
+}
+
+pub struct SelectState {
+    pub packs: Vec<PackProgress>,
+    pub pack_errors: Vec<String>,
+    pub current_pack: usize,
+    pub importer: Box<dyn FileImport>,
+    pub replay_importer: Box<dyn FileImport>,
+    pub import_status: Option<String>,
+    pub custom: PackProgress,
+    pub renaming: Option<(usize, String)>,
+    pub exporter: Box<dyn FileExport>,
+    pub records: Option<LevelRecords>,
 }
 
 pub enum SelectStatus {
-    Level(usize),
+    Level(Rc<LevelData>),
+    LevelWithCourse(Rc<LevelData>, Course),
+    Replay(Box<ReplayFile>),
     Custom,
+    Design(LevelData),
     Settings,
     Credits,
     Idle,
@@ -82,8 +155,74 @@ pub enum SelectStatus {
 
 impl SelectState {
     pub fn new(data: &[Rc<LevelData>]) -> Self {
+        let builtin = LevelPack {
+            meta: PackMeta {
+                name: MAIN_PACK.to_string(),
+                ..Default::default()
+            },
+            levels: data.to_vec(),
+        };
+        let mut packs = vec![PackProgress::new(builtin)];
+        let mut pack_errors = Vec::new();
+        for pack in load_user_packs() {
+            match pack {
+                Ok(p) => packs.push(PackProgress::new(p)),
+                Err(e) => pack_errors.push(e),
+            }
+        }
+        let current_pack = load_current_pack()
+            .and_then(|name| packs.iter().position(|p| p.pack.meta.name == name))
+            .unwrap_or(0);
         Self {
-            solved: data.iter().map(|lvl| load_solve(lvl)).collect(),
+            packs,
+            pack_errors,
+            current_pack,
+            importer: make_importer(),
+            replay_importer: make_importer(),
+            import_status: None,
+            custom: custom_level_progress(),
+            renaming: None,
+            exporter: make_exporter(),
+            records: None,
+        }
+    }
+
+    pub fn show_records(&mut self, lvl: &Rc<LevelData>) {
+        self.records = Some(LevelRecords {
+            level: lvl.clone(),
+            runs: load_runs(lvl),
+            pareto: load_pareto(lvl),
+        });
+    }
+
+    pub fn rename_custom(&mut self, n: usize, name: &str) {
+        rename_custom_level(n, name);
+        self.custom = custom_level_progress();
+    }
+
+    pub fn remove_custom(&mut self, n: usize) {
+        remove_custom_level(n);
+        self.custom = custom_level_progress();
+    }
+
+    pub fn current(&self) -> &PackProgress {
+        &self.packs[self.current_pack]
+    }
+
+    pub fn select_pack(&mut self, n: usize) {
+        self.current_pack = n;
+        save_current_pack(&self.packs[n].pack.meta.name);
+    }
+
+    /// Reloads the user packs after a new one has been installed, and
+    /// switches to the pack called `name`.
+    pub fn reload(&mut self, name: &str) {
+        let levels = self.packs[0].pack.levels.clone();
+        let new = Self::new(&levels);
+        self.packs = new.packs;
+        self.pack_errors = new.pack_errors;
+        if let Some(n) = self.packs.iter().position(|p| p.pack.meta.name == name) {
+            self.select_pack(n);
         }
     }
 }
@@ -92,6 +231,11 @@ fn adjust_view_center(center: &mut Vec2, dir: Direction) {
     *center += Vec2::new(dir.dx() as f32, dir.dy() as f32);
 }
 
+pub enum ShareDialog {
+    Copied(String),
+    Paste { code: String, error: Option<String> },
+}
+
 pub struct EditState {
     pub level_data: Rc<LevelData>,
     pub course: CourseEdit,
@@ -101,11 +245,28 @@ pub struct EditState {
     pub show_keys: bool,
     pub copy_dialog_data: Option<Vec<bool>>,
     pub click_in_gui: bool,
+    pub exporter: Box<dyn FileExport>,
+    pub importer: Box<dyn FileImport>,
+    pub file_error: Option<String>,
+    pub share_dialog: Option<ShareDialog>,
+    pub open_level: Option<SharedCourse>,
+    pub slots: Option<SlotIndex>,
+    pub slot_name: String,
+    pub show_history: bool,
+    pub checkpoints_only: bool,
+    pub checkpoint_name: String,
+    pub course_image: Option<CourseImageOptions>,
+    /// A race on the current course, drawn while editing.
+    pub ghost: Option<GhostRace>,
 }
 
 impl EditState {
     pub fn save_course(&self) {
-        save_course(&self.level_data, self.course.get_course());
+        save_course_slot(
+            &self.level_data,
+            self.course.slot(),
+            self.course.get_course(),
+        );
     }
 
     pub fn new_with_course_edit_and_center(
@@ -122,6 +283,18 @@ impl EditState {
             show_keys: false,
             copy_dialog_data: None,
             click_in_gui: false,
+            exporter: make_exporter(),
+            importer: make_importer(),
+            file_error: None,
+            share_dialog: None,
+            open_level: None,
+            slots: None,
+            slot_name: String::new(),
+            show_history: false,
+            checkpoints_only: false,
+            checkpoint_name: String::new(),
+            course_image: None,
+            ghost: None,
         }
     }
 
@@ -143,8 +316,18 @@ impl EditState {
     }
 
     pub fn new(data: Rc<LevelData>) -> Self {
-        let course = load_course(&data).unwrap_or_default();
-        Self::new_with_course(data, course)
+        let course = CourseEdit::load(data.clone());
+        let view_center = course_center(course.get_course());
+        Self::new_with_course_edit_and_center(data, course, view_center)
+    }
+
+    /// Opens the level with the given course in the active save slot.  The
+    /// course that was there before can still be reached with undo.
+    pub fn with_restored_course(data: Rc<LevelData>, course: Course) -> Self {
+        let mut edit = CourseEdit::load(data.clone());
+        let view_center = course_center(&course);
+        edit.set_course(course);
+        Self::new_with_course_edit_and_center(data, edit, view_center)
     }
 
     pub fn set_tile(&mut self, pos: TileCoord, tile: Tile) {
@@ -227,36 +410,68 @@ pub enum RaceEndStatus {
 
 pub struct RaceState {
     pub level_data: Rc<LevelData>,
-    pub playback: Playback,
+    pub player: Player,
     pub sim: Simulator,
     pub tracker: Tracker,
-    pub round: usize,
     pub status: RaceEndStatus,
     pub view_center: Vec2,
     pub edit: CourseEdit,
     pub show_keys: bool,
     pub exporter: Box<dyn FileExport>,
-    pub animations: Vec<CarAnimation>,
-    pub round_display_time: Duration,
+    pub pareto: Vec<ParetoEntry>,
+    pub replay_options: ReplayOptions,
+    pub show_replay_options: bool,
+    /// The tile where the replay crop rectangle is being dragged from.
+    pub crop_anchor: Option<TileCoord>,
+    pub file_error: Option<String>,
+    pub heatmap: Heatmap,
+    pub show_trails: bool,
+    /// Computed when the heatmap is first turned on.
+    pub traffic: Option<HashMap<TileCoord, TileTraffic>>,
+    pub show_timeline: bool,
+    /// Computed when the timeline is first shown or a car is first
+    /// highlighted.
+    pub timelines: Option<Vec<CarTimeline>>,
+    pub highlighted_car: Option<usize>,
+    /// Whether the view follows the highlighted car.
+    pub follow_car: bool,
+    pub breakpoint_draft: Breakpoint,
+    pub show_breakpoints: bool,
 }
 
 impl RaceState {
+    /// Starts a race on the course and simulates all of its rounds.
     pub fn new(level_data: Rc<LevelData>, edit: CourseEdit, view_center: Vec2) -> Self {
         let cars = level_data.cars;
-        Self {
+        let mut state = Self {
             level_data,
-            playback: Playback::Paused,
+            player: Player::default(),
             sim: Simulator::new(edit.get_course().clone(), cars),
             tracker: Tracker::new(cars),
-            round: 0,
             status: RaceEndStatus::Simulating,
             view_center,
             edit,
             show_keys: false,
             exporter: make_exporter(),
-            animations: Default::default(),
-            round_display_time: Default::default(),
+            pareto: Vec::new(),
+            replay_options: ReplayOptions::default(),
+            show_replay_options: false,
+            crop_anchor: None,
+            file_error: None,
+            heatmap: Heatmap::Off,
+            show_trails: false,
+            traffic: None,
+            show_timeline: false,
+            timelines: None,
+            highlighted_car: None,
+            follow_car: false,
+            breakpoint_draft: Breakpoint::Spawn(0),
+            show_breakpoints: false,
+        };
+        while !state.is_finished() {
+            state.sim_round();
         }
+        state
     }
 
     pub fn rounds_available(&self) -> usize {
@@ -267,92 +482,39 @@ impl RaceState {
         self.sim.is_finished() || self.tracker.is_loop_detected()
     }
 
-    pub fn sim_round(&mut self) {
-        if self.is_finished() {
-            return;
-        }
-        self.sim.run_round();
-        for ev in self.sim.events() {
-            self.tracker.process_event(ev);
+    pub fn set_heatmap(&mut self, heatmap: Heatmap) {
+        self.heatmap = heatmap;
+        if heatmap != Heatmap::Off && self.traffic.is_none() {
+            self.traffic = Some(tile_traffic(&self.tracker));
         }
     }
 
-    pub fn forward(&mut self, animate: bool) {
-        if self.round >= self.rounds_available() - 1 {
-            self.sim_round();
-        }
-        if self.round < self.rounds_available() - 1 {
-            let old_round = self.round;
-            self.round += 1;
-            if animate {
-                let old = &self.tracker.get_cars()[old_round];
-                let new = self.get_cars();
-                self.animations = animations(old, new);
-            } else {
-                self.animations.clear();
-            }
+    pub fn compute_timelines(&mut self) {
+        if self.timelines.is_none() {
+            self.timelines = Some(car_timelines(&self.tracker));
         }
     }
 
-    pub fn check_playback_end(&mut self) {
-        if self.round == self.rounds_available() - 1 && self.is_finished() {
-            self.playback = Playback::Paused;
+    fn sim_round(&mut self) {
+        self.sim.run_round();
+        for ev in self.sim.events() {
+            self.tracker.process_event(ev);
         }
     }
 
     pub fn process_command(&mut self, command: Action, time: Duration) {
-        self.round_display_time = time;
-        match command {
-            Action::Seek(n) => {
-                self.round = n;
-                self.animations.clear();
-            }
-            Action::Start => {
-                self.round = 0;
-                self.animations.clear();
-                self.playback = Playback::Paused;
-            }
-            Action::StepBack => {
-                if self.round > 0 {
-                    self.round -= 1;
-                }
-                self.animations.clear();
-                self.playback = Playback::Paused;
-            }
-            Action::Pause => {
-                self.animations.clear();
-                self.playback = Playback::Paused;
-            }
-            Action::Play => self.playback = Playback::Playing,
-            Action::StepForward => {
-                self.forward(true);
-                self.playback = Playback::Paused;
-            }
-            Action::FastForward => self.playback = Playback::Fast,
-            Action::End => {
-                self.round = self.rounds_available() - 1;
-                self.animations.clear();
-                if self.sim.is_finished() {
-                    self.playback = Playback::Paused;
-                }
-            }
-            Action::Keys => self.show_keys = !self.show_keys,
-            _ => (),
+        if command == Action::Keys {
+            self.show_keys = !self.show_keys;
         }
+        self.player.process_command(command, time, &self.tracker);
     }
 
     pub fn check_advance(&mut self, time: Duration) {
-        if !matches!(self.playback, Playback::Paused)
-            && (time - self.round_display_time) >= self.playback.frame_duration()
-        {
-            self.forward(true);
-            self.round_display_time = time;
-        }
-        self.check_playback_end();
+        self.player.check_advance(time, &self.tracker);
     }
 
     pub fn get_cars(&self) -> &Vec<CarData> {
-        &self.tracker.get_cars()[self.round]
+        self.player.cars(&self.tracker)
     }
 
     pub fn get_course(&self) -> &Course {
@@ -360,17 +522,20 @@ impl RaceState {
     }
 
     pub fn solve_data(&self) -> SolveData {
-        SolveData {
-            tiles: self.sim.get_course().size(),
-            turns: self.rounds_available() - 1,
-        }
+        measure(self.sim.get_course(), &self.tracker)
     }
 
     pub fn check_finished(&mut self) {
         if self.is_finished() {
             self.tracker.compute_final_crashes(self.level_data.cars);
-            if &self.level_data.finish == self.tracker.get_finishes() {
-                save_solve(&self.level_data, &self.solve_data());
+            self.player.divergence = first_divergence(&self.tracker, &self.level_data.finish);
+            let solve = self.solve_data();
+            if self
+                .level_data
+                .is_solved_by(self.tracker.get_finishes(), &solve)
+            {
+                save_solve(&self.level_data, &solve, self.sim.get_course());
+                self.pareto = load_pareto(&self.level_data);
                 self.status = RaceEndStatus::PopupQueued;
             } else {
                 self.status = RaceEndStatus::Finished;
@@ -379,7 +544,67 @@ impl RaceState {
     }
 
     pub fn is_at_end(&self) -> bool {
-        self.is_finished() && self.round == self.rounds_available() - 1
+        self.is_finished() && self.player.round == self.rounds_available() - 1
+    }
+
+    pub fn process_action(&mut self, action: Action) {
+        if let Action::Scroll(dir) = action {
+            adjust_view_center(&mut self.view_center, dir);
+            self.follow_car = false;
+        }
+    }
+}
+
+/// A recorded race opened from a file.  The rounds are already known, so
+/// there is no simulator or course editor behind it.
+pub struct ReplayState {
+    pub level_data: Rc<LevelData>,
+    pub course: Course,
+    pub seed: u64,
+    pub tracker: Tracker,
+    pub player: Player,
+    pub view_center: Vec2,
+    pub show_keys: bool,
+    pub exporter: Box<dyn FileExport>,
+    pub breakpoint_draft: Breakpoint,
+    pub show_breakpoints: bool,
+    pub file_error: Option<String>,
+}
+
+impl ReplayState {
+    pub fn new(replay: ReplayFile) -> Self {
+        let player = Player {
+            divergence: first_divergence(&replay.tracker, &replay.level.finish),
+            ..Default::default()
+        };
+        Self {
+            level_data: Rc::new(replay.level),
+            view_center: course_center(&replay.course),
+            course: replay.course,
+            seed: replay.seed,
+            tracker: replay.tracker,
+            player,
+            show_keys: false,
+            exporter: make_exporter(),
+            breakpoint_draft: Breakpoint::Spawn(0),
+            show_breakpoints: false,
+            file_error: None,
+        }
+    }
+
+    pub fn process_command(&mut self, command: Action, time: Duration) {
+        if command == Action::Keys {
+            self.show_keys = !self.show_keys;
+        }
+        self.player.process_command(command, time, &self.tracker);
+    }
+
+    pub fn check_advance(&mut self, time: Duration) {
+        self.player.check_advance(time, &self.tracker);
+    }
+
+    pub fn get_cars(&self) -> &Vec<CarData> {
+        self.player.cars(&self.tracker)
     }
 
     pub fn process_action(&mut self, action: Action) {
@@ -390,9 +615,18 @@ impl RaceState {
 }
 
 pub struct CustomSpecState {
+    pub name: String,
     pub cars: usize,
     pub finish: String,
-    pub finish_is_valid: bool,
+    pub banned: EnumMap<TileType, bool>,
+    pub tutorial_text: String,
+    pub limit_rounds: bool,
+    pub max_rounds: usize,
+    pub error: Option<String>,
+    pub exporter: Box<dyn FileExport>,
+    pub export_status: Option<String>,
+    /// Levels that the design must differ from.
+    pub existing: Vec<LevelData>,
 }
 
 pub enum DialogResponse<T> {
@@ -401,8 +635,6 @@ pub enum DialogResponse<T> {
     Waiting,
 }
 
-static DIGIT_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new("[0-9]+").unwrap());
-
 impl Default for CustomSpecState {
     fn default() -> Self {
         Self::new()
@@ -411,37 +643,78 @@ impl Default for CustomSpecState {
 
 impl CustomSpecState {
     pub fn new() -> Self {
-        Self {
+        let mut state = Self {
+            name: "Custom Level".to_string(),
             cars: 0,
             finish: "".to_string(),
-            finish_is_valid: true,
-        }
-    }
-
-    fn parse_finish(cars: usize, finish: &str) -> impl Iterator<Item = Option<usize>> + use<'_> {
-        let mut seen = Vec::new();
-        seen.resize(cars, false);
-        DIGIT_RE
-            .find_iter(finish)
-            .map(move |s| match s.as_str().parse::<usize>() {
-                Ok(n) => {
-                    if seen.get(n) == Some(&false) {
-                        seen[n] = true;
-                        Some(n)
-                    } else {
-                        None
-                    }
-                }
-                _ => None,
-            })
-    }
-
-    pub fn check_finish(&mut self) {
-        self.finish_is_valid = Self::parse_finish(self.cars, &self.finish).all(|r| r.is_some());
-    }
-
-    pub fn get_finish(&self) -> Option<Vec<usize>> {
-        Self::parse_finish(self.cars, &self.finish).collect()
+            banned: Default::default(),
+            tutorial_text: String::new(),
+            limit_rounds: false,
+            max_rounds: 100,
+            error: None,
+            exporter: make_exporter(),
+            export_status: None,
+            existing: Vec::new(),
+        };
+        state.check();
+        state
+    }
+
+    /// Rejects designs with the same rules as one of `levels`, since such a
+    /// level would share its saved courses and records.
+    pub fn avoiding(mut self, levels: Vec<LevelData>) -> Self {
+        self.existing = levels;
+        self.check();
+        self
+    }
+
+    /// Opens the designer on a copy of an existing level.
+    pub fn from_level(lvl: &LevelData) -> Self {
+        let finish: Vec<String> = lvl.finish.iter().map(|n| n.to_string()).collect();
+        let mut state = Self {
+            name: lvl.name.clone(),
+            cars: lvl.cars,
+            finish: finish.join(", "),
+            banned: lvl.banned,
+            tutorial_text: lvl.tutorial_text.clone().unwrap_or_default(),
+            limit_rounds: lvl.max_rounds.is_some(),
+            max_rounds: lvl.max_rounds.unwrap_or(100),
+            ..Self::new()
+        };
+        state.check();
+        state
+    }
+
+    /// Builds the level described by the designer, or explains why the
+    /// design is not a valid level.
+    pub fn to_level(&self) -> Result<LevelData, String> {
+        let finish =
+            parse_finish(&self.finish, self.cars).map_err(|e| format!("Finish order, {e}"))?;
+        let tutorial_text = self.tutorial_text.trim();
+        let lvl = LevelData {
+            name: self.name.trim().to_string(),
+            cars: self.cars,
+            finish,
+            tutorial: None,
+            banned: self.banned,
+            tutorial_text: (!tutorial_text.is_empty()).then(|| tutorial_text.to_string()),
+            max_rounds: self.limit_rounds.then_some(self.max_rounds),
+            par: None,
+        };
+        lvl.validate()?;
+        Ok(lvl)
+    }
+
+    pub fn check(&mut self) {
+        self.error = match self.to_level() {
+            Ok(lvl) if self.existing.iter().any(|l| l.same_rules(&lvl)) => Some(
+                "a custom level with the same cars, finish order, round limit and allowed \
+                 track already exists"
+                    .to_string(),
+            ),
+            Ok(_) => None,
+            Err(e) => Some(e),
+        };
     }
 }
 
@@ -450,12 +723,18 @@ pub enum SettingsMenu {
     Display,
     Keys,
     Help,
+    Profile,
     ChooseKey(Action),
 }
 
 pub struct SettingsState {
     pub last: Box<dyn Screen>,
     pub menu: SettingsMenu,
+    pub exporter: Box<dyn FileExport>,
+    pub importer: Box<dyn FileImport>,
+    pub import_policy: ImportPolicy,
+    pub import_settings: bool,
+    pub profile_status: Option<String>,
 }
 
 impl SettingsState {
@@ -463,6 +742,11 @@ impl SettingsState {
         Self {
             last,
             menu: SettingsMenu::Main,
+            exporter: make_exporter(),
+            importer: make_importer(),
+            import_policy: ImportPolicy::KeepBest,
+            import_settings: true,
+            profile_status: None,
         }
     }
 }
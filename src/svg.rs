@@ -0,0 +1,214 @@
+//! Vector drawings of courses, for putting track diagrams into documents.
+
+use std::fmt::Write;
+
+use crate::{
+    course::{Course, bounding_rect},
+    direction::{DihedralElement, Direction},
+    tile::{Tile, TileType},
+};
+
+static ROAD_COLOR: &str = "#a6cbcc";
+static KERB_COLOR: &str = "#e4661c";
+static ARROW_COLOR: &str = "#52676a";
+static GRID_COLOR: &str = "#b0b0b0";
+static ROAD_WIDTH: f32 = 0.56;
+static KERB_WIDTH: f32 = 0.66;
+
+/// The ways that a car can drive through a tile in its default orientation,
+/// as the direction it enters in and the direction it leaves in.
+const fn moves(tile_type: TileType) -> &'static [(Direction, Direction)] {
+    use Direction::*;
+    use TileType::*;
+    match tile_type {
+        Straight | Finish => &[(Up, Up)],
+        Turn => &[(Up, Left)],
+        LightTurns => &[(Up, Left), (Up, Right)],
+        LightForwardTurn => &[(Up, Up), (Up, Left)],
+        Merge => &[(Up, Up), (Left, Up)],
+        YieldIntersection | LightIntersection => &[(Up, Up), (Left, Left)],
+    }
+}
+
+/// A path from the edge where a car enters to the edge where it leaves, in
+/// coordinates centered on the tile.
+fn move_path((from, to): (Direction, Direction), scale: f32) -> String {
+    let (x0, y0) = (
+        -0.5 * scale * from.dx() as f32,
+        -0.5 * scale * from.dy() as f32,
+    );
+    let (x1, y1) = (0.5 * scale * to.dx() as f32, 0.5 * scale * to.dy() as f32);
+    format!("M {x0} {y0} Q 0 0 {x1} {y1}")
+}
+
+fn arrow_head(to: Direction) -> String {
+    let (dx, dy) = (to.dx() as f32, to.dy() as f32);
+    let (tip_x, tip_y) = (0.42 * dx, 0.42 * dy);
+    let (base_x, base_y) = (0.22 * dx, 0.22 * dy);
+    // Perpendicular to the direction of travel.
+    let (px, py) = (-0.12 * dy, 0.12 * dx);
+    format!(
+        "M {tip_x} {tip_y} L {} {} L {} {} Z",
+        base_x + px,
+        base_y + py,
+        base_x - px,
+        base_y - py
+    )
+}
+
+fn tile_svg(out: &mut String, tile: &Tile) {
+    let moves = moves(tile.tile_type);
+    for &(color, width) in &[(KERB_COLOR, KERB_WIDTH), (ROAD_COLOR, ROAD_WIDTH)] {
+        for &mv in moves {
+            let _ = writeln!(
+                out,
+                r#"<path d="{}" fill="none" stroke="{color}" stroke-width="{width}"/>"#,
+                move_path(mv, 1.0)
+            );
+        }
+    }
+    if tile.tile_type == TileType::Finish {
+        for n in 0..8 {
+            let x = -0.32 + 0.08 * (n as f32);
+            for (row, y) in [(0, -0.08), (1, 0.0)] {
+                let fill = if (n + row) % 2 == 0 { "black" } else { "white" };
+                let _ = writeln!(
+                    out,
+                    r#"<rect x="{x}" y="{y}" width="0.08" height="0.08" fill="{fill}"/>"#
+                );
+            }
+        }
+    }
+    for &mv in moves {
+        let _ = writeln!(
+            out,
+            r#"<path d="{}" fill="none" stroke="{ARROW_COLOR}" stroke-width="0.08"/>"#,
+            move_path(mv, 0.5)
+        );
+        let _ = writeln!(
+            out,
+            r#"<path d="{}" fill="{ARROW_COLOR}"/>"#,
+            arrow_head(mv.1)
+        );
+    }
+    if tile.tile_type.has_lights() {
+        for x in [-0.4, 0.4] {
+            let _ = writeln!(
+                out,
+                r#"<circle cx="{x}" cy="0.42" r="0.05" fill="limegreen" stroke="black" stroke-width="0.02"/>"#
+            );
+        }
+    }
+    if matches!(
+        tile.tile_type,
+        TileType::Merge | TileType::YieldIntersection
+    ) {
+        let _ = writeln!(
+            out,
+            r#"<path d="M 0.28 0.28 L 0.44 0.28 L 0.36 0.42 Z" fill="white" stroke="red" stroke-width="0.03"/>"#
+        );
+    }
+}
+
+/// The SVG transform that maps coordinates centered on a tile in its
+/// default orientation to the transformed tile at `(x, y)`.
+fn tile_transform(transform: DihedralElement, x: isize, y: isize) -> String {
+    let r = transform * Direction::Right;
+    let d = transform * Direction::Down;
+    format!(
+        "matrix({} {} {} {} {} {})",
+        r.dx(),
+        r.dy(),
+        d.dx(),
+        d.dy(),
+        x as f32 + 0.5,
+        y as f32 + 0.5
+    )
+}
+
+/// Draws a course as an SVG document.  Each tile is one unit wide and is
+/// scaled to `tile_size` pixels.  If `coordinates` is set, the tile
+/// coordinates are written along the top and left edges.
+pub fn course_to_svg(
+    course: &Course,
+    tile_size: f32,
+    grid_lines: bool,
+    coordinates: bool,
+) -> String {
+    let (xrange, yrange) = if course.is_empty() {
+        (0..=0, 0..=0)
+    } else {
+        bounding_rect(course.keys())
+    };
+    let margin = if coordinates { 0.6 } else { 0.0 };
+    let (x0, y0) = (
+        *xrange.start() as f32 - margin,
+        *yrange.start() as f32 - margin,
+    );
+    let width = (xrange.end() - xrange.start() + 1) as f32 + margin;
+    let height = (yrange.end() - yrange.start() + 1) as f32 + margin;
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="{x0} {y0} {width} {height}">"#,
+        width * tile_size,
+        height * tile_size
+    );
+    let mut tiles: Vec<_> = course.iter().collect();
+    tiles.sort_by_key(|(pos, _)| (pos.1, pos.0));
+    for (pos, tile) in tiles {
+        let _ = writeln!(
+            out,
+            r#"<g transform="{}">"#,
+            tile_transform(tile.transform, pos.0, pos.1)
+        );
+        tile_svg(&mut out, tile);
+        out.push_str("</g>\n");
+    }
+    if grid_lines {
+        let _ = writeln!(out, r#"<g stroke="{GRID_COLOR}" stroke-width="0.02">"#);
+        for x in *xrange.start()..=xrange.end() + 1 {
+            let _ = writeln!(
+                out,
+                r#"<line x1="{x}" y1="{}" x2="{x}" y2="{}"/>"#,
+                yrange.start(),
+                yrange.end() + 1
+            );
+        }
+        for y in *yrange.start()..=yrange.end() + 1 {
+            let _ = writeln!(
+                out,
+                r#"<line x1="{}" y1="{y}" x2="{}" y2="{y}"/>"#,
+                xrange.start(),
+                xrange.end() + 1
+            );
+        }
+        out.push_str("</g>\n");
+    }
+    if coordinates {
+        out.push_str(
+            r#"<g font-family="sans-serif" font-size="0.3" text-anchor="middle" dominant-baseline="central">"#,
+        );
+        out.push('\n');
+        let label_pos = -margin / 2.0;
+        for x in xrange.clone() {
+            let _ = writeln!(
+                out,
+                r#"<text x="{}" y="{}">{x}</text>"#,
+                x as f32 + 0.5,
+                *yrange.start() as f32 + label_pos
+            );
+        }
+        for y in yrange.clone() {
+            let _ = writeln!(
+                out,
+                r#"<text x="{}" y="{}">{y}</text>"#,
+                *xrange.start() as f32 + label_pos,
+                y as f32 + 0.5
+            );
+        }
+        out.push_str("</g>\n");
+    }
+    out.push_str("</svg>\n");
+    out
+}
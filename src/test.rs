@@ -1,20 +1,49 @@
+use std::{
+    collections::BTreeMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    rc::Rc,
+    time::Duration,
+};
+
+use base64::engine::{Engine, general_purpose::URL_SAFE_NO_PAD};
 use strum::IntoEnumIterator;
 
 use crate::{
     combine::combine_options,
-    course::TileCoord,
+    course::{Course, CourseEditState, TileCoord, bounding_rect},
     direction::{
         DihedralElement, Direction, ROTATIONS, reflection_along, rotation_for, trans_for_dirs,
     },
+    finish::parse_finish,
+    ghost::{GhostRace, GhostView},
+    history::{MAX_HISTORY, MAX_HISTORY_TILES, UndoTree},
+    input::Action,
+    level::{LevelData, MAX_CARS, SolveData},
+    metrics::{Metric, cost, footprint, intersections},
+    pack::{builtin_pack, levels_to_toml, parse_pack},
     path::track_tile,
-    save::courses_from_toml,
+    playback::{
+        Breakpoint, Playback, Player, animations, next_event, prev_event, reverse_animations,
+        rounds_due,
+    },
+    records::{
+        Medal, ParetoEntry, add_to_pareto_front, best_courses, best_entry, par_comparison,
+        run_race, simulate,
+    },
+    render::{RgbaImage, SoftwareGraphics, Sprites, render_race},
+    replay_file::ReplayFile,
+    save::{ImportPolicy, Profile, ProfileLevel, SlotIndex, courses_from_toml, import_choice},
+    share::{decode, encode},
     simulator::{
         CarCoord, STRAIGHT_ENTRANCE, STRAIGHT_EXIT, Simulator, TURN_ENTRANCE, TURN_EXIT,
         is_entrance, is_entrance_id, is_exit, is_exit_id,
     },
+    states::CustomSpecState,
+    svg::course_to_svg,
     tile::{Tile, TileType},
     tracker::Tracker,
-    ui::loader::load_levels,
+    traffic::{CarEnd, car_timelines, first_divergence, route, tile_traffic, trail},
+    ui::{loader::load_levels, race::PLAYBACK_ACTIONS, settings::Settings, viewer::VIEWER_ACTIONS},
 };
 
 #[test]
@@ -205,3 +234,764 @@ fn test_combine() {
         }
     }
 }
+
+#[test]
+fn test_profile_roundtrip() {
+    let solutions = courses_from_toml(include_str!("../res/solutions.toml")).unwrap();
+    let levels: Vec<LevelData> = load_levels().iter().map(|l| l.as_ref().clone()).collect();
+    let mut slots = SlotIndex::default();
+    let second = slots.add("Second".to_string());
+    slots.active = second;
+    let courses = BTreeMap::from([
+        (0, solutions["Level 3"].clone()),
+        (slots.slots[second].id, solutions["Level 4"].clone()),
+    ]);
+    let profile = Profile {
+        version: 1,
+        levels: vec![ProfileLevel {
+            level: levels[3].clone(),
+            slots,
+            courses,
+            pareto: Vec::new(),
+            solve: Some(SolveData {
+                tiles: 12,
+                turns: 30,
+                ..Default::default()
+            }),
+        }],
+        custom_levels: levels,
+        settings: Settings::default(),
+    };
+    let loaded = Profile::from_bytes(&profile.to_bytes().unwrap()).unwrap();
+    let entry = &loaded.levels[0];
+    assert_eq!(entry.level.name, profile.levels[0].level.name);
+    assert_eq!(entry.level.banned, profile.levels[0].level.banned);
+    assert_eq!(entry.courses, profile.levels[0].courses);
+    assert_eq!(entry.slots.active, second);
+    let names = entry.slots.slots.iter().map(|s| s.name.as_str());
+    assert!(names.eq(["Main", "Second"]));
+    assert!(entry.solve == profile.levels[0].solve);
+    assert_eq!(loaded.custom_levels.len(), profile.custom_levels.len());
+}
+
+#[test]
+fn test_level_msgpack_positional() {
+    let mut level = load_levels()[3].as_ref().clone();
+    level.tutorial_text = None;
+    level.max_rounds = Some(40);
+    let data = rmp_serde::to_vec(&level).unwrap();
+    let loaded: LevelData = rmp_serde::from_slice(&data).unwrap();
+    assert_eq!(loaded.tutorial_text, None);
+    assert_eq!(loaded.max_rounds, Some(40));
+    assert!(loaded.par == level.par);
+}
+
+#[test]
+fn test_import_choice() {
+    let good = SolveData {
+        tiles: 10,
+        turns: 20,
+        ..Default::default()
+    };
+    let bad = SolveData {
+        tiles: 12,
+        turns: 30,
+        ..Default::default()
+    };
+    let (take, solve) = import_choice(ImportPolicy::Overwrite, Some(bad), Some(good), true);
+    assert!(take && solve == Some(bad));
+    let (take, solve) = import_choice(ImportPolicy::Overwrite, None, Some(good), true);
+    assert!(take && solve == Some(good));
+    let (take, solve) = import_choice(ImportPolicy::Skip, Some(good), Some(bad), true);
+    assert!(!take && solve == Some(bad));
+    let (take, solve) = import_choice(ImportPolicy::Skip, Some(good), None, false);
+    assert!(take && solve == Some(good));
+    let (take, solve) = import_choice(ImportPolicy::KeepBest, Some(good), Some(bad), true);
+    assert!(take && solve == Some(good));
+    let (take, solve) = import_choice(ImportPolicy::KeepBest, Some(bad), Some(good), true);
+    assert!(!take && solve == Some(good));
+    let (take, solve) = import_choice(ImportPolicy::KeepBest, None, Some(good), false);
+    assert!(take && solve == Some(good));
+}
+
+#[test]
+fn test_share_code_roundtrip() {
+    let solutions = courses_from_toml(include_str!("../res/solutions.toml")).unwrap();
+    for (n, lvl) in load_levels().iter().enumerate() {
+        if let Some(course) = solutions.get(&format!("Level {n}")) {
+            let shared = decode(&encode(lvl, course)).unwrap();
+            assert_eq!(&shared.course, course);
+            assert_eq!(shared.level.name, lvl.name);
+            assert_eq!(shared.level.cars, lvl.cars);
+            assert_eq!(shared.level.finish, lvl.finish);
+            assert_eq!(shared.level.banned, lvl.banned);
+        }
+    }
+    assert!(decode("race:not a share code").is_err());
+    let levels = load_levels();
+    let code = encode(&levels[1], &solutions["Level 1"]);
+    let body = code.strip_prefix("race:").unwrap();
+    let compressed = URL_SAFE_NO_PAD.decode(body).unwrap();
+    let mut data = miniz_oxide::inflate::decompress_to_vec(&compressed).unwrap();
+    data.push(0);
+    let extended = miniz_oxide::deflate::compress_to_vec(&data, 10);
+    let code = format!("race:{}", URL_SAFE_NO_PAD.encode(extended));
+    assert!(decode(&code).is_err());
+    let crowded = LevelData {
+        cars: MAX_CARS + 1,
+        ..levels[1].clone()
+    };
+    assert!(decode(&encode(&crowded, &solutions["Level 1"])).is_err());
+}
+
+/// Runs the solution to a built-in level to the end.
+fn solved_race(n: usize) -> (Rc<LevelData>, Course, Tracker) {
+    let mut solutions = courses_from_toml(include_str!("../res/solutions.toml")).unwrap();
+    let level = load_levels().swap_remove(n);
+    let course = solutions.remove(&format!("Level {n}")).unwrap();
+    let mut tracker = run_race(&course, level.cars);
+    tracker.compute_final_crashes(level.cars);
+    (level, course, tracker)
+}
+
+#[test]
+fn test_replay_file_roundtrip() {
+    let (level, course, tracker) = solved_race(4);
+    let bytes = ReplayFile::encode(&level, &course, 1234, &tracker).unwrap();
+    let replay = ReplayFile::from_bytes(&bytes).unwrap();
+    assert_eq!(replay.level.name, level.name);
+    assert_eq!(replay.course, course);
+    assert_eq!(replay.seed, 1234);
+    assert!(replay.tracker.get_cars() == tracker.get_cars());
+    assert_eq!(replay.tracker.get_finishes(), tracker.get_finishes());
+    assert_eq!(replay.tracker.get_crashes(), tracker.get_crashes());
+    assert_eq!(
+        replay.tracker.is_loop_detected(),
+        tracker.is_loop_detected()
+    );
+    assert!(ReplayFile::from_bytes(&bytes[..bytes.len() / 2]).is_err());
+    let fewer_cars = LevelData {
+        cars: 1,
+        finish: Vec::new(),
+        ..(*level).clone()
+    };
+    let bytes = ReplayFile::encode(&fewer_cars, &course, 1234, &tracker).unwrap();
+    assert!(ReplayFile::from_bytes(&bytes).is_err());
+    let unnamed = LevelData {
+        name: String::new(),
+        ..(*level).clone()
+    };
+    let bytes = ReplayFile::encode(&unnamed, &course, 1234, &tracker).unwrap();
+    assert!(ReplayFile::from_bytes(&bytes).is_err());
+}
+
+#[test]
+fn test_course_svg() {
+    let solutions = courses_from_toml(include_str!("../res/solutions.toml")).unwrap();
+    let course = &solutions["Level 5"];
+    let plain = course_to_svg(course, 64.0, false, false);
+    assert!(plain.starts_with("<svg ") && plain.ends_with("</svg>\n"));
+    assert_eq!(plain.matches("<g transform=").count(), course.size());
+    assert!(!plain.contains("<line") && !plain.contains("<text"));
+    let (xrange, yrange) = bounding_rect(course.keys());
+    let annotated = course_to_svg(course, 64.0, true, true);
+    assert_eq!(
+        annotated.matches("<line").count(),
+        xrange.clone().count() + yrange.clone().count() + 2
+    );
+    assert_eq!(
+        annotated.matches("<text").count(),
+        xrange.count() + yrange.count()
+    );
+}
+
+#[test]
+fn test_tile_traffic() {
+    let (level, _, tracker) = solved_race(5);
+    let traffic = tile_traffic(&tracker);
+    let visits: usize = traffic.values().map(|t| t.visits).sum();
+    assert!(visits >= level.cars);
+    let car_rounds: usize = tracker.get_cars().iter().map(|r| r.len()).sum();
+    let stops: usize = traffic.values().map(|t| t.stops).sum();
+    assert!(visits + stops <= car_rounds);
+    let round = tracker.rounds_available() / 2;
+    for car in &tracker.get_cars()[round] {
+        let positions = trail(&tracker, car.id, round, 3);
+        assert!((1..=4).contains(&positions.len()));
+        assert_eq!(positions.last(), Some(&car.pos));
+        let ahead = route(&tracker, car.id, round);
+        assert_eq!(ahead.first(), Some(&car.pos));
+        assert!(ahead.len() < tracker.rounds_available() - round + 1);
+    }
+}
+
+#[test]
+fn test_car_timelines() {
+    let (level, _, tracker) = solved_race(5);
+    let timelines = car_timelines(&tracker);
+    assert_eq!(timelines.len(), level.cars);
+    for (place, &id) in tracker.get_finishes().iter().enumerate() {
+        let t = &timelines[id];
+        assert_eq!(t.place, Some(place + 1));
+        let spawn = t.spawn.unwrap();
+        match t.end {
+            Some(CarEnd::Finished(end)) => assert!(spawn < end && t.stops < end - spawn),
+            _ => panic!("car {id} should have finished"),
+        }
+    }
+}
+
+#[test]
+fn test_breakpoints() {
+    let (level, _, tracker) = solved_race(5);
+    assert_eq!(first_divergence(&tracker, &level.finish), None);
+    let timelines = car_timelines(&tracker);
+    let first = tracker.get_finishes()[0];
+    let finish_round = match timelines[first].end {
+        Some(CarEnd::Finished(r)) => r,
+        _ => panic!("car {first} should have finished"),
+    };
+    let breakpoints = [Breakpoint::Finish(first)];
+    assert_eq!(
+        next_event(&tracker, &breakpoints, 0, None),
+        Some(finish_round)
+    );
+    assert_eq!(next_event(&tracker, &breakpoints, finish_round, None), None);
+    let end = tracker.rounds_available() - 1;
+    assert_eq!(
+        prev_event(&tracker, &breakpoints, end, None),
+        Some(finish_round)
+    );
+    let spawn = [Breakpoint::Spawn(0)];
+    assert_eq!(next_event(&tracker, &spawn, 0, None), timelines[0].spawn);
+    let mut goal = level.finish.clone();
+    goal.swap(0, 1);
+    let divergence = first_divergence(&tracker, &goal).unwrap();
+    assert_eq!(divergence.place, 0);
+    assert_eq!(divergence.actual, Some(first));
+    assert_eq!(divergence.round, finish_round);
+    let diverge = [Breakpoint::Divergence];
+    assert_eq!(
+        next_event(&tracker, &diverge, 0, Some(divergence.round)),
+        Some(finish_round)
+    );
+    let mut player = Player {
+        breakpoints: breakpoints.to_vec(),
+        ..Default::default()
+    };
+    player.process_command(Action::Play, Duration::ZERO, &tracker);
+    player.check_advance(Duration::from_secs(3600), &tracker);
+    assert_eq!(player.round, finish_round);
+    assert_eq!(player.playback, Playback::Paused);
+    player.process_command(Action::Seek(end + 10), Duration::ZERO, &tracker);
+    assert_eq!(player.round, end);
+    player.process_command(Action::PrevEvent, Duration::ZERO, &tracker);
+    assert_eq!(player.round, finish_round);
+}
+
+#[test]
+fn test_playback_keys_dispatched() {
+    let settings = Settings::default();
+    for &action in settings.keys.keys() {
+        if action.is_active_when_racing() && !matches!(action, Action::Scroll(_)) {
+            assert!(PLAYBACK_ACTIONS.contains(&action), "{action:?} in a race");
+            assert!(VIEWER_ACTIONS.contains(&action), "{action:?} in the viewer");
+        }
+    }
+}
+
+#[test]
+fn test_reverse_playback() {
+    let (_, _, tracker) = solved_race(5);
+    let cars = tracker.get_cars();
+    let round = (0..cars.len() - 1)
+        .find(|&r| {
+            !cars[r].is_empty()
+                && cars[r] != cars[r + 1]
+                && cars[r]
+                    .iter()
+                    .all(|c| cars[r + 1].iter().any(|d| d.id == c.id))
+        })
+        .unwrap();
+    let forward = animations(&cars[round], &cars[round + 1]);
+    let backward = reverse_animations(&cars[round + 1], &cars[round]);
+    assert_eq!(backward.len(), cars[round].len());
+    for anim in &backward {
+        let fwd = forward.iter().find(|a| a.id == anim.id).unwrap();
+        for t in [0.0, 0.25, 1.0] {
+            let p = anim.position_at_time(t).pos;
+            let q = fwd.position_at_time(1.0 - t).pos;
+            assert!((p - q).length() < 1e-5, "car {} at {t}", anim.id);
+        }
+    }
+    let doubled = Playback::Playing.round_duration(2.0);
+    assert_eq!(doubled, Duration::from_millis(250));
+    assert_eq!(
+        Playback::Paused.round_duration(10.0),
+        Playback::Paused.frame_duration()
+    );
+    assert_eq!(rounds_due(Duration::from_millis(16), doubled), 0);
+    assert_eq!(
+        rounds_due(Duration::from_millis(16), Duration::from_millis(5)),
+        3
+    );
+}
+
+#[test]
+fn test_ghost_race() {
+    let (level, course, _) = solved_race(5);
+    let mut ghost = GhostRace::new(level.cars);
+    ghost.update(&course, 0, true, &level);
+    while !ghost.is_finished() {
+        ghost.update(&course, 0, true, &level);
+    }
+    assert!(ghost.has_race());
+    assert_eq!(ghost.tracker.get_finishes(), &level.finish);
+    assert_eq!(ghost.divergence, None);
+    ghost.view = GhostView::Round(usize::MAX);
+    ghost.advance(Duration::ZERO);
+    assert_eq!(ghost.round, ghost.tracker.rounds_available() - 1);
+    let empty = Course::new_with_hasher_and_ptr_kind(Default::default());
+    ghost.update(&empty, 0, false, &level);
+    assert!(ghost.has_race());
+    ghost.update(&empty, 1, false, &level);
+    assert!(!ghost.has_race());
+    assert_eq!(ghost.tracker.rounds_available(), 1);
+    let limited = LevelData {
+        max_rounds: Some(5),
+        ..level.as_ref().clone()
+    };
+    ghost.update(&course, 2, true, &limited);
+    while !ghost.is_finished() {
+        ghost.update(&course, 2, true, &limited);
+    }
+    assert!(ghost.has_race());
+    assert_eq!(ghost.tracker.rounds_available(), 6);
+}
+
+fn straight_line(len: isize) -> CourseEditState {
+    let mut course = Course::new_with_hasher_and_ptr_kind(Default::default());
+    for x in 0..len {
+        course.insert_mut(TileCoord(x, 0), Tile::default_for_type(TileType::Straight));
+    }
+    CourseEditState::from_course(course)
+}
+
+#[test]
+fn test_undo_tree_branches() {
+    let mut tree = UndoTree::new(straight_line(0));
+    tree.push(straight_line(1));
+    tree.push(straight_line(2));
+    assert!(tree.undo());
+    tree.push(straight_line(3));
+    assert_eq!(tree.nodes().len(), 4);
+    assert!(tree.undo());
+    assert!(tree.redo());
+    assert_eq!(tree.current().course().size(), 3);
+    let old_branch = (0..4)
+        .find(|&n| tree.nodes()[n].state.course().size() == 2)
+        .unwrap();
+    assert!(tree.jump(old_branch));
+    assert!(tree.undo());
+    assert!(tree.redo());
+    assert_eq!(tree.current().course().size(), 2);
+    assert_eq!(tree.display_order().len(), 4);
+}
+
+#[test]
+fn test_undo_tree_prune() {
+    let mut tree = UndoTree::new(straight_line(0));
+    tree.set_checkpoint(Some("start".to_string()));
+    for n in 1..(MAX_HISTORY as isize + 20) {
+        tree.push(straight_line(n));
+    }
+    assert!(tree.nodes().len() <= MAX_HISTORY);
+    assert_eq!(tree.current().course().size(), MAX_HISTORY + 19);
+    assert!(tree.nodes().iter().any(|n| n.checkpoint.is_some()));
+    let mut size = tree.current().course().size();
+    while tree.undo() {
+        assert_eq!(tree.current().course().size(), size - 1);
+        size -= 1;
+    }
+    let big = (0..100).map(|n| straight_line(1000 + n));
+    let mut tree = UndoTree::new(straight_line(0));
+    for state in big {
+        tree.push(state);
+    }
+    let tiles: usize = tree.nodes().iter().map(|n| n.state.course().size()).sum();
+    assert!(tiles <= MAX_HISTORY_TILES);
+    assert_eq!(tree.current().course().size(), 1099);
+}
+
+#[test]
+fn test_undo_tree_prune_branch() {
+    let mut tree = UndoTree::new(straight_line(0));
+    for n in 1..=5 {
+        tree.push(straight_line(n));
+    }
+    for _ in 0..3 {
+        assert!(tree.undo());
+    }
+    for n in 0..3 {
+        tree.push(straight_line(1000 + n));
+    }
+    assert!(tree.jump(5));
+    // The last push goes one node over the limit.
+    for n in 6..(MAX_HISTORY as isize - 2) {
+        tree.push(straight_line(n));
+    }
+    assert!(tree.nodes().len() <= MAX_HISTORY);
+    let size = |n: usize| tree.nodes()[n].state.course().size();
+    assert!((0..tree.nodes().len()).all(|n| size(n) < 1000));
+    for (n, node) in tree.nodes().iter().enumerate() {
+        if let Some(p) = node.parent {
+            assert_eq!(size(n), size(p) + 1);
+        }
+    }
+}
+
+#[test]
+fn test_parse_pack() {
+    assert_eq!(builtin_pack().levels.len(), load_levels().len());
+    let pack = parse_pack(
+        r#"
+[pack]
+name = "Training"
+author = "Team"
+
+[[levels]]
+name = "Swap"
+cars = 2
+finish = [1, 0]
+"#,
+        "Unused",
+    )
+    .unwrap();
+    assert_eq!(pack.meta.name, "Training");
+    assert_eq!(pack.meta.version, "");
+    assert_eq!(pack.levels[0].finish, vec![1, 0]);
+    let unnamed = parse_pack("[[levels]]\nname = \"A\"\ncars = 1\nfinish = [0]\n", "File");
+    assert_eq!(unnamed.unwrap().meta.name, "File");
+    let err = parse_pack(
+        "[[levels]]\nname = \"Bad\"\ncars = 2\nfinish = [0, 2]\n",
+        "File",
+    );
+    assert!(err.is_err_and(|e| e.contains("Bad")));
+    assert!(parse_pack("[[levels]]\nname = \"Bad\"\n", "File").is_err());
+}
+
+#[test]
+fn test_designed_level_toml() {
+    let mut spec = CustomSpecState::new();
+    spec.name = "Swap".to_string();
+    spec.cars = 4;
+    spec.finish = "1 0 3 2".to_string();
+    spec.banned[TileType::Merge] = true;
+    spec.tutorial_text = "Swap each pair of cars.".to_string();
+    spec.limit_rounds = true;
+    spec.max_rounds = 50;
+    spec.check();
+    assert!(spec.error.is_none());
+    let lvl = spec.to_level().unwrap();
+    let pack = parse_pack(&levels_to_toml(&[lvl.clone()]), "Test").unwrap();
+    let parsed = &pack.levels[0];
+    assert_eq!(parsed.finish, lvl.finish);
+    assert!(parsed.banned[TileType::Merge]);
+    assert!(!parsed.banned[TileType::Turn]);
+    assert_eq!(parsed.tutorial_text, lvl.tutorial_text);
+    assert_eq!(parsed.max_rounds, Some(50));
+    let solve = SolveData {
+        tiles: 10,
+        turns: 51,
+        ..Default::default()
+    };
+    assert!(!lvl.is_solved_by(&[1, 0, 3, 2], &solve));
+    spec.banned[TileType::Finish] = true;
+    spec.check();
+    assert!(spec.error.is_some());
+}
+
+#[test]
+fn test_designer_from_level() {
+    for lvl in load_levels() {
+        let spec = CustomSpecState::from_level(&lvl);
+        assert!(spec.error.is_none());
+        let copy = spec.to_level().unwrap();
+        assert_eq!(copy.name, lvl.name);
+        assert_eq!(copy.cars, lvl.cars);
+        assert_eq!(copy.finish, lvl.finish);
+        assert_eq!(copy.banned, lvl.banned);
+        let mut spec = CustomSpecState::from_level(&lvl).avoiding(vec![copy]);
+        assert!(spec.error.is_some());
+        spec.name = "Copy".to_string();
+        spec.check();
+        assert!(spec.error.is_some());
+        spec.limit_rounds = !spec.limit_rounds;
+        spec.check();
+        assert!(spec.error.is_none());
+    }
+}
+
+#[test]
+fn test_level_hash_includes_banned() {
+    let hash = |lvl: &LevelData| {
+        let mut hasher = DefaultHasher::new();
+        lvl.hash(&mut hasher);
+        hasher.finish()
+    };
+    let lvl = load_levels()[0].as_ref().clone();
+    let mut unbanned = lvl.clone();
+    unbanned.banned = Default::default();
+    assert_ne!(hash(&lvl), hash(&unbanned));
+}
+
+#[test]
+fn test_finish_expressions() {
+    let levels = load_levels();
+    let exprs = [
+        ("Tutorial: all finish", "0..10"),
+        ("Evens", "evens(10)"),
+        ("Offset", "interleave(odds(10), evens(10))"),
+        ("Groups of four", "repeat(0..4, 2, 8)"),
+        ("Period six", "2..24 step 6"),
+        ("Reverse", "rev(0..=7)"),
+        ("Extra", "0, odds(8)"),
+    ];
+    for (name, expr) in exprs {
+        let lvl = levels.iter().find(|l| l.name == name).unwrap();
+        assert_eq!(parse_finish(expr, lvl.cars).unwrap(), lvl.finish, "{name}");
+    }
+    assert_eq!(parse_finish("[1, 0, 3 2]", 4).unwrap(), vec![1, 0, 3, 2]);
+    assert_eq!(
+        parse_finish("repeat(rev(0..2), 3)", 6).unwrap(),
+        vec![1, 0, 3, 2, 5, 4]
+    );
+    assert_eq!(parse_finish("", 3).unwrap(), Vec::<usize>::new());
+    let err = parse_finish("0 1 1", 3).unwrap_err();
+    assert_eq!((err.line, err.column), (1, 5));
+    let err = parse_finish("0..3,\nfoo(1)", 3).unwrap_err();
+    assert_eq!((err.line, err.column), (2, 1));
+    let err = parse_finish("rev(0..3", 3).unwrap_err();
+    assert_eq!(err.column, 9);
+    assert!(parse_finish("0..5 step 0", 5).is_err());
+    assert!(parse_finish("0..1000000000", 5).is_err());
+}
+
+#[test]
+fn test_par() {
+    let solutions = courses_from_toml(include_str!("../res/solutions.toml")).unwrap();
+    for (n, lvl) in load_levels().iter().enumerate() {
+        let solve = simulate(lvl, &solutions[&format!("Level {n}")]);
+        assert!(solve.is_some() && lvl.par == solve, "{}", lvl.name);
+    }
+    let par = SolveData {
+        tiles: 20,
+        turns: 40,
+        cost: Some(30),
+        ..Default::default()
+    };
+    let solve = |tiles, turns| SolveData {
+        tiles,
+        turns,
+        ..Default::default()
+    };
+    let medal = |tiles, turns| Medal::for_solve(&solve(tiles, turns), &par, Metric::Tiles);
+    assert_eq!(medal(20, 40), Some(Medal::Gold));
+    assert_eq!(medal(18, 40), Some(Medal::Gold));
+    assert_eq!(medal(25, 30), Some(Medal::Silver));
+    assert_eq!(medal(20, 60), Some(Medal::Bronze));
+    assert_eq!(medal(31, 40), None);
+    assert_eq!(
+        par_comparison(&solve(25, 40), &par, Metric::Tiles),
+        "+25% tiles, par rounds"
+    );
+    let cheap = SolveData {
+        cost: Some(30),
+        ..solve(31, 40)
+    };
+    assert_eq!(
+        Medal::for_solve(&cheap, &par, Metric::Cost),
+        Some(Medal::Gold)
+    );
+    assert_eq!(
+        par_comparison(&cheap, &par, Metric::Cost),
+        "par cost, par rounds"
+    );
+    assert_eq!(
+        par_comparison(&solve(25, 40), &par, Metric::Cost),
+        "+25% tiles, par rounds"
+    );
+}
+
+#[test]
+fn test_pareto_front() {
+    let entry = |tiles, turns| ParetoEntry {
+        solve: SolveData {
+            tiles,
+            turns,
+            ..Default::default()
+        },
+        course: Course::new_with_hasher_and_ptr_kind(Default::default()),
+    };
+    let mut front = Vec::new();
+    assert!(add_to_pareto_front(&mut front, entry(20, 40)));
+    assert!(add_to_pareto_front(&mut front, entry(30, 25)));
+    assert!(add_to_pareto_front(&mut front, entry(10, 50)));
+    assert!(!add_to_pareto_front(&mut front, entry(20, 40)));
+    assert!(!add_to_pareto_front(&mut front, entry(25, 45)));
+    assert!(add_to_pareto_front(&mut front, entry(20, 25)));
+    let solves: Vec<_> = front
+        .iter()
+        .map(|e| (e.solve.tiles, e.solve.turns))
+        .collect();
+    assert_eq!(solves, vec![(10, 50), (20, 25)]);
+}
+
+#[test]
+fn test_metrics() {
+    let mut course = straight_line(3).course().clone();
+    course.insert_mut(
+        TileCoord(1, 2),
+        Tile::default_for_type(TileType::LightIntersection),
+    );
+    assert_eq!(footprint(&course), 9);
+    assert_eq!(cost(&course), 7);
+    assert_eq!(intersections(&course), 1);
+    for lvl in load_levels() {
+        let par = lvl.par.unwrap();
+        assert!(par.footprint >= Some(par.tiles), "{}", lvl.name);
+        assert!(
+            lvl.finish.is_empty() || par.distance > Some(0),
+            "{}",
+            lvl.name
+        );
+    }
+    let entry = |tiles, turns, cost| ParetoEntry {
+        solve: SolveData {
+            tiles,
+            turns,
+            cost: Some(cost),
+            ..Default::default()
+        },
+        course: Course::new_with_hasher_and_ptr_kind(Default::default()),
+    };
+    let mut front = Vec::new();
+    assert!(add_to_pareto_front(&mut front, entry(10, 40, 30)));
+    assert!(add_to_pareto_front(&mut front, entry(12, 40, 20)));
+    assert!(add_to_pareto_front(&mut front, entry(15, 30, 25)));
+    let best = |metric| -> Vec<usize> {
+        best_courses(&front, metric)
+            .iter()
+            .filter_map(|e| metric.value(&e.solve))
+            .collect()
+    };
+    assert_eq!(best(Metric::Tiles), vec![10, 15]);
+    assert_eq!(best(Metric::Cost), vec![20, 25]);
+    assert!(!add_to_pareto_front(&mut front, entry(11, 45, 25)));
+    assert_eq!(front.len(), 3);
+    assert_eq!(best_entry(&front, Metric::Tiles).unwrap().solve.tiles, 10);
+    assert_eq!(
+        best_entry(&front, Metric::Cost).unwrap().solve.cost,
+        Some(20)
+    );
+    let old = SolveData {
+        tiles: 8,
+        turns: 40,
+        ..Default::default()
+    };
+    let new = front[1].solve;
+    assert_eq!(old.combine(&new).cost, Some(20));
+    assert!(add_to_pareto_front(&mut front, entry(9, 40, 30)));
+    assert!(!add_to_pareto_front(
+        &mut front,
+        ParetoEntry {
+            solve: SolveData {
+                tiles: 9,
+                turns: 40,
+                ..Default::default()
+            },
+            course: Course::new_with_hasher_and_ptr_kind(Default::default()),
+        }
+    ));
+    assert_eq!(best(Metric::Cost), vec![20, 25]);
+    let unmeasured = ParetoEntry {
+        solve: SolveData {
+            tiles: 7,
+            turns: 35,
+            ..Default::default()
+        },
+        course: Course::new_with_hasher_and_ptr_kind(Default::default()),
+    };
+    assert!(add_to_pareto_front(&mut front, unmeasured));
+    assert!(add_to_pareto_front(&mut front, entry(7, 35, 15)));
+    assert_eq!(best(Metric::Cost), vec![15, 25]);
+    assert!(front.iter().all(|e| e.solve.cost.is_some()));
+}
+
+fn rotate_cw(img: &RgbaImage) -> RgbaImage {
+    let mut rotated = RgbaImage::new(img.height, img.width, [0; 4]);
+    for y in 0..rotated.height {
+        for x in 0..rotated.width {
+            let p = img.pixel(y, img.height - 1 - x);
+            let i = 4 * (y * rotated.width + x) as usize;
+            rotated.data[i..i + 4].copy_from_slice(&p);
+        }
+    }
+    rotated
+}
+
+#[test]
+fn test_render_dihedral() {
+    let sprites = Sprites::load();
+    for t in TileType::iter() {
+        let render = |transform| {
+            let mut course = Course::new_with_hasher_and_ptr_kind(Default::default());
+            let tile = Tile {
+                transform,
+                ..Tile::default_for_type(t)
+            };
+            course.insert_mut(TileCoord(2, -1), tile);
+            let mut graphics = SoftwareGraphics::for_course(&sprites, &course, 32, &[1.0; 3]);
+            graphics.draw_course(&course);
+            graphics.draw.image
+        };
+        let id = render(DihedralElement::Id);
+        assert_ne!(id, RgbaImage::new(32, 32, [0xFF; 4]));
+        assert_eq!(render(DihedralElement::Rot90), rotate_cw(&id));
+        assert_eq!(render(DihedralElement::Rot180), rotate_cw(&rotate_cw(&id)));
+    }
+}
+
+/// Compares an image with the one saved in `res/golden`.  If
+/// `RACE_UPDATE_GOLDEN` is set, the saved image is replaced instead.
+fn check_golden(name: &str, img: &RgbaImage) {
+    let path = format!("{}/res/golden/{name}.png", env!("CARGO_MANIFEST_DIR"));
+    if std::env::var_os("RACE_UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, img.to_png().unwrap()).unwrap();
+        return;
+    }
+    let bytes = std::fs::read(&path).unwrap_or_else(|e| panic!("can't read {path}: {e}"));
+    let golden = RgbaImage::from_png(&bytes).unwrap();
+    assert!(&golden == img, "{name} differs from {path}");
+}
+
+#[test]
+fn test_render_golden() {
+    let sprites = Sprites::load();
+    for n in [1, 5] {
+        let (_, course, tracker) = solved_race(n);
+        let mut graphics = SoftwareGraphics::for_course(&sprites, &course, 24, &[1.0; 3]);
+        let round = tracker.rounds_available() / 2;
+        graphics.round = round;
+        graphics.draw_course(&course);
+        graphics.draw_cars(&tracker.get_cars()[round], &[], 0.0);
+        check_golden(&format!("level{n}"), &graphics.draw.image);
+        let mut frames = 0;
+        render_race(&mut graphics, &course, &tracker, 3, |_| {
+            frames += 1;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(frames, 1 + 3 * (tracker.rounds_available() - 1));
+    }
+}
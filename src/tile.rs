@@ -25,6 +25,18 @@ impl TileType {
         )
     }
 
+    /// The price of a tile, used by the cost metric.  Lights and
+    /// intersections are more expensive than plain road.
+    pub const fn cost(self) -> usize {
+        use TileType::*;
+        match self {
+            Straight | Turn | Finish => 1,
+            Merge | YieldIntersection => 2,
+            LightTurns | LightForwardTurn => 3,
+            LightIntersection => 4,
+        }
+    }
+
     pub const fn name(self) -> &'static str {
         use TileType::*;
         match self {
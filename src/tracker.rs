@@ -1,4 +1,5 @@
 use crate::simulator::{CarData, SimEvent};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::hash::BuildHasher;
 
 type HashBuilder = hashbrown::DefaultHashBuilder;
@@ -101,3 +102,50 @@ impl Tracker {
         self.loop_detected
     }
 }
+
+#[derive(Serialize)]
+struct TrackerRef<'a> {
+    rounds: &'a [Vec<CarData>],
+    finished: &'a [usize],
+    crashed: &'a [bool],
+}
+
+#[derive(Deserialize)]
+struct TrackerData {
+    rounds: Vec<Vec<CarData>>,
+    finished: Vec<usize>,
+    crashed: Vec<bool>,
+}
+
+impl Serialize for Tracker {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        TrackerRef {
+            rounds: self.get_cars(),
+            finished: &self.finished,
+            crashed: &self.crashed,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Tracker {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let data = TrackerData::deserialize(deserializer)?;
+        if data.rounds.is_empty() {
+            return Err(serde::de::Error::custom(
+                "a race must have at least one round",
+            ));
+        }
+        let mut round_data = CarDataLookup::default();
+        let mut loop_detected = false;
+        for round in data.rounds {
+            loop_detected |= round_data.add(round);
+        }
+        Ok(Self {
+            round_data,
+            finished: data.finished,
+            crashed: data.crashed,
+            loop_detected,
+        })
+    }
+}
@@ -0,0 +1,195 @@
+//! Statistics about where cars drive and wait over the course of a race.
+
+use hashbrown::HashMap;
+use strum::EnumIter;
+
+use crate::{
+    course::TileCoord,
+    simulator::{CarCoord, CarData},
+    tracker::Tracker,
+};
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct TileTraffic {
+    /// The number of times a car entered the tile.
+    pub visits: usize,
+    /// The number of rounds that cars spent waiting to enter the tile.
+    pub stops: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, EnumIter, Debug, Default)]
+pub enum Heatmap {
+    #[default]
+    Off,
+    Traffic,
+    Stops,
+}
+
+impl Heatmap {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Off => "Off",
+            Self::Traffic => "Traffic",
+            Self::Stops => "Stops",
+        }
+    }
+
+    pub const fn value(self, traffic: &TileTraffic) -> usize {
+        match self {
+            Self::Off => 0,
+            Self::Traffic => traffic.visits,
+            Self::Stops => traffic.stops,
+        }
+    }
+}
+
+fn tile_for(car: &CarData) -> TileCoord {
+    car.tile_pos().into()
+}
+
+/// Counts the visits and stops on each tile over the whole race.  Tiles that
+/// no car ever reached are not included.
+pub fn tile_traffic(tracker: &Tracker) -> HashMap<TileCoord, TileTraffic> {
+    let mut traffic: HashMap<TileCoord, TileTraffic> = HashMap::new();
+    let mut last: Vec<Option<CarData>> = Vec::new();
+    for round in tracker.get_cars() {
+        for car in round {
+            if car.id >= last.len() {
+                last.resize(car.id + 1, None);
+            }
+            let entry = traffic.entry(tile_for(car)).or_default();
+            match last[car.id] {
+                Some(prev) if prev == *car => entry.stops += 1,
+                Some(prev) if tile_for(&prev) == tile_for(car) => (),
+                _ => entry.visits += 1,
+            }
+            last[car.id] = Some(*car);
+        }
+    }
+    traffic
+}
+
+/// The positions of a car in up to `len` rounds before `round`, oldest
+/// first, ending with its position in `round`.  The trail stops at the
+/// first round in which the car was not on the course.
+pub fn trail(tracker: &Tracker, id: usize, round: usize, len: usize) -> Vec<CarCoord> {
+    let rounds = &tracker.get_cars()[round.saturating_sub(len)..=round];
+    let mut positions: Vec<CarCoord> = rounds
+        .iter()
+        .rev()
+        .map_while(|cars| cars.iter().find(|c| c.id == id).map(|c| c.pos))
+        .collect();
+    positions.reverse();
+    positions
+}
+
+/// The positions of a car from `round` until it leaves the course or the
+/// race ends.
+pub fn route(tracker: &Tracker, id: usize, round: usize) -> Vec<CarCoord> {
+    tracker.get_cars()[round..]
+        .iter()
+        .map_while(|cars| cars.iter().find(|c| c.id == id).map(|c| c.pos))
+        .collect()
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CarEnd {
+    Finished(usize),
+    Crashed(usize),
+}
+
+/// When a car entered and left the course.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CarTimeline {
+    pub id: usize,
+    /// The first round in which the car is on the course.
+    pub spawn: Option<usize>,
+    /// The first round in which the car is no longer on the course, or
+    /// `None` if it never left.
+    pub end: Option<CarEnd>,
+    /// The number of rounds in which the car didn't move.
+    pub stops: usize,
+    /// The position of the car in the finish order, starting from 1.
+    pub place: Option<usize>,
+}
+
+/// Summarizes the race from the point of view of each car.
+pub fn car_timelines(tracker: &Tracker) -> Vec<CarTimeline> {
+    let num_cars = tracker.get_crashes().len();
+    let mut timelines: Vec<CarTimeline> = (0..num_cars)
+        .map(|id| CarTimeline {
+            id,
+            spawn: None,
+            end: None,
+            stops: 0,
+            place: tracker
+                .get_finishes()
+                .iter()
+                .position(|&f| f == id)
+                .map(|p| p + 1),
+        })
+        .collect();
+    let mut last: Vec<Option<(usize, CarData)>> = vec![None; num_cars];
+    for (round, cars) in tracker.get_cars().iter().enumerate() {
+        for car in cars.iter().filter(|c| c.id < num_cars) {
+            let timeline = &mut timelines[car.id];
+            timeline.spawn.get_or_insert(round);
+            if let Some((_, prev)) = last[car.id]
+                && prev == *car
+            {
+                timeline.stops += 1;
+            }
+            last[car.id] = Some((round, *car));
+        }
+    }
+    let final_round = tracker.rounds_available() - 1;
+    for (timeline, last) in timelines.iter_mut().zip(last) {
+        let Some((round, _)) = last else {
+            continue;
+        };
+        if timeline.place.is_some() {
+            timeline.end = Some(CarEnd::Finished(round + 1));
+        } else if round < final_round {
+            timeline.end = Some(CarEnd::Crashed(round + 1));
+        }
+    }
+    timelines
+}
+
+/// The first place where the actual finish order differs from the goal.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Divergence {
+    /// The index into the finish order, starting from 0.
+    pub place: usize,
+    pub expected: Option<usize>,
+    pub actual: Option<usize>,
+    /// The round in which the wrong car finished or the expected car
+    /// crashed, or the last round if neither happened.
+    pub round: usize,
+}
+
+/// Compares the finish order to `goal`.  Returns `None` if they match.
+pub fn first_divergence(tracker: &Tracker, goal: &[usize]) -> Option<Divergence> {
+    let finishes = tracker.get_finishes();
+    let place = (0..finishes.len().max(goal.len())).find(|&n| finishes.get(n) != goal.get(n))?;
+    let expected = goal.get(place).copied();
+    let actual = finishes.get(place).copied();
+    let timelines = car_timelines(tracker);
+    let finish_round = actual
+        .and_then(|id| timelines.get(id)?.end)
+        .map(|(CarEnd::Finished(r) | CarEnd::Crashed(r))| r);
+    let crash_round = expected.and_then(|id| match timelines.get(id)?.end {
+        Some(CarEnd::Crashed(r)) => Some(r),
+        _ => None,
+    });
+    let round = match (finish_round, crash_round) {
+        (Some(a), Some(b)) => a.min(b),
+        (a, b) => a.or(b).unwrap_or(tracker.rounds_available() - 1),
+    };
+    Some(Divergence {
+        place,
+        expected,
+        actual,
+        round,
+    })
+}
@@ -0,0 +1,234 @@
+use notan::{
+    app::{Color, Graphics},
+    draw::{CreateDraw, DrawShapes, DrawTextSection, DrawTransform},
+    egui::{self, Context, Slider},
+    math::{Affine2, Mat2, Vec2},
+};
+use strum::{EnumIter, IntoEnumIterator};
+
+use crate::{
+    course::{Course, bounding_rect},
+    states::EditState,
+    svg::course_to_svg,
+};
+
+use super::{
+    graphics::{TILE_SIZE, TileGraphics},
+    loader::Resources,
+    race::{anh, gfx_size_for},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, EnumIter)]
+pub enum ImageFormat {
+    Png,
+    Svg,
+}
+
+impl ImageFormat {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Png => "PNG",
+            Self::Svg => "SVG",
+        }
+    }
+
+    pub const fn file_name(self) -> &'static str {
+        match self {
+            Self::Png => "course.png",
+            Self::Svg => "course.svg",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct CourseImageOptions {
+    pub format: ImageFormat,
+    /// The width of a tile in pixels.
+    pub tile_size: u32,
+    pub grid_lines: bool,
+    pub coordinates: bool,
+}
+
+impl Default for CourseImageOptions {
+    fn default() -> Self {
+        Self {
+            format: ImageFormat::Png,
+            tile_size: 128,
+            grid_lines: false,
+            coordinates: false,
+        }
+    }
+}
+
+/// The space left for coordinate labels, as a fraction of a tile.
+const LABEL_MARGIN: f32 = 0.6;
+
+/// Draws the course without cars into a render texture and encodes it as a
+/// PNG.
+fn course_png(
+    gfx: &mut Graphics,
+    res: &Resources,
+    course: &Course,
+    options: &CourseImageOptions,
+    bg_color: &[f32; 3],
+) -> Result<Vec<u8>, anyhow::Error> {
+    let zoom = options.tile_size as f32 / TILE_SIZE;
+    let tsz = options.tile_size as f32;
+    let (xrange, yrange) = bounding_rect(course.keys());
+    let margin = if options.coordinates {
+        (LABEL_MARGIN * tsz).round()
+    } else {
+        0.0
+    };
+    let width = gfx_size_for(xrange.end() - xrange.start() + 1, zoom) + margin as u32;
+    let height = gfx_size_for(yrange.end() - yrange.start() + 1, zoom) + margin as u32;
+    let texture = gfx
+        .create_render_texture(width, height)
+        .build()
+        .map_err(anh)?;
+    let mut graphics = TileGraphics {
+        res,
+        zoom,
+        bg_color,
+        draw: texture.create_draw(),
+        round: 0,
+    };
+    // The texture is read back upside down, so everything is drawn flipped.
+    let (xs, ys) = (*xrange.start() as f32 * tsz, *yrange.start() as f32 * tsz);
+    let aff = Affine2::from_mat2_translation(
+        Mat2::from_diagonal(Vec2::new(1.0, -1.0)),
+        Vec2::new(margin - xs, height as f32 - margin + ys),
+    );
+    graphics.draw.transform().push(aff.into());
+    graphics.draw_course(course);
+    let (left, top) = (xs, ys);
+    let (right, bottom) = (
+        (*xrange.end() + 1) as f32 * tsz,
+        (*yrange.end() + 1) as f32 * tsz,
+    );
+    if options.grid_lines {
+        let grid_color = Color::from_rgb(0.69, 0.69, 0.69);
+        let stroke = (0.02 * tsz).max(1.0);
+        for x in *xrange.start()..=xrange.end() + 1 {
+            let x = x as f32 * tsz;
+            graphics
+                .draw
+                .line((x, top), (x, bottom))
+                .color(grid_color)
+                .width(stroke);
+        }
+        for y in *yrange.start()..=yrange.end() + 1 {
+            let y = y as f32 * tsz;
+            graphics
+                .draw
+                .line((left, y), (right, y))
+                .color(grid_color)
+                .width(stroke);
+        }
+    }
+    if options.coordinates {
+        let size = 0.3 * tsz;
+        for x in xrange.clone() {
+            graphics
+                .draw
+                .text(&res.font, &x.to_string())
+                .position((x as f32 + 0.5) * tsz, top - 0.5 * margin)
+                .size(size)
+                .color(Color::BLACK)
+                .h_align_center()
+                .v_align_middle();
+        }
+        for y in yrange.clone() {
+            graphics
+                .draw
+                .text(&res.font, &y.to_string())
+                .position(left - 0.5 * margin, (y as f32 + 0.5) * tsz)
+                .size(size)
+                .color(Color::BLACK)
+                .h_align_center()
+                .v_align_middle();
+        }
+    }
+    graphics.draw.transform().pop();
+    gfx.render_to(&texture, &graphics.draw);
+    let mut pix = vec![0; 4 * (width as usize) * (height as usize)];
+    gfx.read_pixels(&texture).read_to(&mut pix).map_err(anh)?;
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&pix)?;
+    Ok(out)
+}
+
+fn export_course_image(
+    gfx: &mut Graphics,
+    res: &Resources,
+    state: &mut EditState,
+    options: &CourseImageOptions,
+    bg_color: &[f32; 3],
+) -> Result<(), anyhow::Error> {
+    let course = state.course.get_course();
+    if course.is_empty() {
+        anyhow::bail!("the course is empty");
+    }
+    let bytes = match options.format {
+        ImageFormat::Png => course_png(gfx, res, course, options, bg_color)?,
+        ImageFormat::Svg => course_to_svg(
+            course,
+            options.tile_size as f32,
+            options.grid_lines,
+            options.coordinates,
+        )
+        .into_bytes(),
+    };
+    state.exporter.set_save_action(
+        Box::new(move |w| {
+            w.write_all(&bytes)?;
+            Ok(())
+        }),
+        options.format.file_name(),
+    )
+}
+
+/// Shows the course image options while they are open.
+pub(super) fn draw_course_image_window(
+    ctx: &Context,
+    gfx: &mut Graphics,
+    res: &Resources,
+    bg_color: &[f32; 3],
+    state: &mut EditState,
+) {
+    let Some(options) = &mut state.course_image else {
+        return;
+    };
+    let mut open = true;
+    let mut save = false;
+    egui::Window::new("Export course image")
+        .open(&mut open)
+        .show(ctx, |ui| {
+            egui::ComboBox::from_label("Format")
+                .selected_text(options.format.name())
+                .show_ui(ui, |ui| {
+                    for f in ImageFormat::iter() {
+                        ui.selectable_value(&mut options.format, f, f.name());
+                    }
+                });
+            ui.add(
+                Slider::new(&mut options.tile_size, 16..=512)
+                    .logarithmic(true)
+                    .suffix(" px")
+                    .text("Tile size"),
+            );
+            ui.checkbox(&mut options.grid_lines, "Grid lines");
+            ui.checkbox(&mut options.coordinates, "Coordinates");
+            save = ui.button("Save").clicked();
+        });
+    let options = *options;
+    if save && let Err(e) = export_course_image(gfx, res, state, &options, bg_color) {
+        state.file_error = Some(e.to_string());
+    }
+    if !open {
+        state.course_image = None;
+    }
+}
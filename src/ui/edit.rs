@@ -3,7 +3,7 @@ use std::rc::Rc;
 use notan::{
     app::{App, Graphics, Plugins},
     draw::CreateDraw,
-    egui::{self, Context, EguiPluginSugar, Rect, Ui},
+    egui::{self, Context, EguiPluginSugar, Rect, Slider, Ui},
     math::Vec2,
     prelude::KeyCode,
 };
@@ -11,19 +11,26 @@ use strum::IntoEnumIterator;
 
 use crate::{
     combine::combine,
+    course::CourseEdit,
     direction::DihedralElement,
+    ghost::{GhostRace, GhostView},
     input::Action,
     level::LevelData,
     path::track_tile,
     playback::Playback,
-    save::{course_is_nonempty, course_to_vec, load_course},
+    save::{
+        course_from_toml, course_is_nonempty, course_to_toml, load_course, load_slots,
+        remove_course_slot, save_course_slot, save_slots,
+    },
     selection::{DragState, SelectState, drag_tiles, selection_rect},
-    states::{DialogResponse, EditState, TrackSelection},
+    share::{decode, encode},
+    states::{DialogResponse, EditState, ShareDialog, TrackSelection},
     tile::TileType,
     tooltip::TooltipState,
 };
 
 use super::{
+    course_image::draw_course_image_window,
     graphics::{TILE_SIZE, TileGraphics, allocate_ui_space, create_draw_masked, get_draw_offset},
     input::{check_key_press, key_name, mouse_coords, mouse_coords_car},
     loader::{GuiImage, Resources},
@@ -198,6 +205,7 @@ fn draw_track_panel(
 }
 
 static OVERLAY_ALPHA: f32 = 0.8;
+static GHOST_ALPHA: f32 = 0.5;
 
 fn draw_course_edit(
     app: &App,
@@ -230,30 +238,46 @@ fn draw_course_edit(
         }
     }
     graphics.draw_course(state.course.get_course());
+    if let Some(ghost) = &state.ghost {
+        if settings.smooth_animation && !ghost.animations.is_empty() {
+            let t = (app.timer.elapsed() - ghost.round_display_time)
+                .div_duration_f32(Playback::Playing.frame_duration());
+            for anim in &ghost.animations {
+                graphics.draw_ghost_car(anim.id, &anim.position_at_time(t), GHOST_ALPHA);
+            }
+        } else {
+            for car in ghost.get_cars() {
+                graphics.draw_ghost_car(car.id, &(*car).into(), GHOST_ALPHA);
+            }
+        }
+    }
     if !mouse_in_gui {
         let pos = mouse_coords(app, settings, offset);
         match &state.track_selection {
             TrackSelection::Draw(tile) => {
-                graphics.draw_tile(*tile, pos).alpha(OVERLAY_ALPHA);
+                graphics.draw_tile_with_alpha(*tile, pos, OVERLAY_ALPHA);
             }
             TrackSelection::Erase => {
-                graphics
-                    .draw_tile_sprite(&res.erase.textures[0], DihedralElement::Id, pos)
-                    .alpha(OVERLAY_ALPHA);
+                graphics.draw_tile_sprite(
+                    &res.erase.textures[0],
+                    DihedralElement::Id,
+                    pos,
+                    OVERLAY_ALPHA,
+                );
             }
             TrackSelection::Modify(selection) => {
                 if let DragState::Dragging(drag) = &selection.drag {
                     for (new_pos, tile) in
                         drag_tiles(&selection.selection, drag, state.course.get_course(), pos)
                     {
-                        graphics.draw_tile(tile, new_pos).alpha(OVERLAY_ALPHA);
+                        graphics.draw_tile_with_alpha(tile, new_pos, OVERLAY_ALPHA);
                     }
                 }
             }
             TrackSelection::Path(path) => {
                 for p in path.path().windows(2) {
                     let (track_pos, tile) = track_tile(p[0], p[1]);
-                    graphics.draw_tile(tile, track_pos).alpha(OVERLAY_ALPHA);
+                    graphics.draw_tile_with_alpha(tile, track_pos, OVERLAY_ALPHA);
                 }
                 if path.path().is_empty() {
                     if let Some(mpos) = mouse_coords_car(app, settings, offset) {
@@ -391,7 +415,11 @@ fn draw_tutorial(res: &Resources, settings: &Settings, state: &EditState, ctx: &
                 )
             }
         }
-        _ => (),
+        _ => {
+            if let Some(text) = &state.level_data.tutorial_text {
+                tutorial_text(ctx, text);
+            }
+        }
     }
 }
 
@@ -417,10 +445,10 @@ fn draw_copy_dialog<'a>(
 
 fn process_debug_commands(app: &App, state: &mut EditState) {
     if app.keyboard.was_pressed(KeyCode::F1) {
-        let mut lev = std::collections::BTreeMap::new();
-        let course = course_to_vec(state.course.get_course());
-        lev.insert(state.level_data.name.clone(), course);
-        println!("{}", toml::to_string(&lev).unwrap());
+        println!(
+            "{}",
+            course_to_toml(&state.level_data.name, state.course.get_course())
+        );
     } else if app.keyboard.was_pressed(KeyCode::F2) {
         let solutions =
             crate::save::courses_from_toml(include_str!("../../res/solutions.toml")).unwrap();
@@ -430,6 +458,281 @@ fn process_debug_commands(app: &App, state: &mut EditState) {
     }
 }
 
+fn export_course(state: &mut EditState) -> Result<(), anyhow::Error> {
+    let data = course_to_toml(&state.level_data.name, state.course.get_course());
+    state.exporter.set_save_action(
+        Box::new(move |w| {
+            w.write_all(data.as_bytes())?;
+            Ok(())
+        }),
+        "course.toml",
+    )
+}
+
+fn import_course(state: &mut EditState, data: &[u8]) -> Result<(), anyhow::Error> {
+    let course = course_from_toml(&state.level_data.name, std::str::from_utf8(data)?)?;
+    if !course.is_empty() {
+        state.track_selection = TrackSelection::Modify(SelectState::load_external(course));
+    }
+    Ok(())
+}
+
+fn update_file_dialogs(state: &mut EditState, ctx: &Context) {
+    let _ = state.exporter.update(ctx);
+    let result = match state.importer.update(ctx) {
+        Ok(Some(data)) => import_course(state, &data),
+        Ok(None) => Ok(()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        state.file_error = Some(e.to_string());
+    }
+    if let Some(err) = &state.file_error {
+        let mut open = true;
+        egui::Window::new("Error").open(&mut open).show(ctx, |ui| {
+            ui.label(err);
+        });
+        if !open {
+            state.file_error = None;
+        }
+    }
+}
+
+fn draw_share_dialog(ctx: &Context, state: &mut EditState) {
+    let Some(dialog) = &mut state.share_dialog else {
+        return;
+    };
+    let mut close = false;
+    let mut loaded = None;
+    match dialog {
+        ShareDialog::Copied(code) => {
+            egui::Window::new("Share code").show(ctx, |ui| {
+                ui.label("The share code has been copied to the clipboard.");
+                ui.add(
+                    egui::TextEdit::multiline(&mut code.as_str()).desired_width(3.0 * TILE_SIZE),
+                );
+                if ui.button("Close").clicked() {
+                    close = true;
+                }
+            });
+        }
+        ShareDialog::Paste { code, error } => {
+            egui::Window::new("Paste share code").show(ctx, |ui| {
+                ui.label("Paste a share code here:");
+                if ui
+                    .add(egui::TextEdit::multiline(code).desired_width(3.0 * TILE_SIZE))
+                    .changed()
+                {
+                    *error = None;
+                }
+                if let Some(e) = error {
+                    ui.colored_label(egui::Color32::RED, e.as_str());
+                }
+                ui.horizontal(|ui| {
+                    let insert = ui.button("Insert into course").clicked();
+                    let open = ui.button("Open as new level").clicked();
+                    if insert || open {
+                        match decode(code) {
+                            Ok(shared) => loaded = Some((shared, open)),
+                            Err(e) => *error = Some(e.to_string()),
+                        }
+                    }
+                    if ui.button("Cancel").clicked() {
+                        close = true;
+                    }
+                });
+            });
+        }
+    }
+    if let Some((shared, open)) = loaded {
+        close = true;
+        if open {
+            state.open_level = Some(shared);
+        } else if !shared.course.is_empty() {
+            state.track_selection =
+                TrackSelection::Modify(SelectState::load_external(shared.course));
+        }
+    }
+    if close {
+        state.share_dialog = None;
+    }
+}
+
+fn draw_slot_window(ctx: &Context, state: &mut EditState) {
+    let Some(slots) = &mut state.slots else {
+        return;
+    };
+    let best = slots.best();
+    let mut switch_to = None;
+    let mut rename = false;
+    let mut duplicate = false;
+    let mut add = false;
+    let mut delete = false;
+    let mut close = false;
+    egui::Window::new("Save slots").show(ctx, |ui| {
+        egui::Grid::new("slot grid").show(ui, |ui| {
+            for (n, slot) in slots.slots.iter().enumerate() {
+                let star = if best == Some(n) { " \u{2605}" } else { "" };
+                let text = format!("{}{star}", slot.name);
+                if ui.selectable_label(n == slots.active, text).clicked() && n != slots.active {
+                    switch_to = Some(n);
+                }
+                if let Some(solve) = slot.solve {
+                    ui.label(format!("{} tiles", solve.tiles));
+                    ui.label(format!("{} rounds", solve.turns));
+                }
+                ui.end_row();
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut state.slot_name);
+            rename = ui.button("Rename").clicked() && !state.slot_name.is_empty();
+        });
+        ui.horizontal(|ui| {
+            duplicate = ui.button("Duplicate").clicked();
+            add = ui.button("New").clicked();
+            delete = ui
+                .add_enabled(slots.slots.len() > 1, egui::Button::new("Delete"))
+                .clicked();
+            close = ui.button("Close").clicked();
+        });
+    });
+    if rename || duplicate || add || delete || switch_to.is_some() {
+        // Solves may have been recorded in storage since the window was
+        // opened, so the changes are made to a fresh copy.
+        *slots = load_slots(&state.level_data);
+    }
+    if rename {
+        let active = slots.active;
+        slots.slots[active].name = state.slot_name.clone();
+        save_slots(&state.level_data, slots);
+    }
+    if duplicate {
+        let name = format!("{} (copy)", slots.active_slot().name);
+        let n = slots.add(name);
+        save_course_slot(
+            &state.level_data,
+            slots.slots[n].id,
+            state.course.get_course(),
+        );
+        switch_to = Some(n);
+    } else if add {
+        let n = slots.add(format!("Slot {}", slots.slots.len() + 1));
+        switch_to = Some(n);
+    } else if delete && slots.slots.len() > 1 {
+        let id = slots.active_slot().id;
+        slots.remove(slots.active);
+        remove_course_slot(&state.level_data, id);
+        switch_to = Some(slots.active);
+    }
+    if let Some(n) = switch_to {
+        if !delete {
+            state.course.flush_history();
+        }
+        slots.active = n;
+        save_slots(&state.level_data, slots);
+        state.slot_name = slots.active_slot().name.clone();
+        state.course = CourseEdit::load(state.level_data.clone());
+        state.track_selection = Default::default();
+    }
+    if close {
+        state.slots = None;
+    }
+}
+
+fn draw_history_window(ctx: &Context, state: &mut EditState) {
+    if !state.show_history {
+        return;
+    }
+    let mut jump = None;
+    let mut checkpoint = None;
+    let mut open = true;
+    egui::Window::new("History")
+        .open(&mut open)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut state.checkpoint_name);
+                if ui.button("Add checkpoint").clicked() && !state.checkpoint_name.is_empty() {
+                    checkpoint = Some(Some(state.checkpoint_name.clone()));
+                }
+                if ui.button("Remove checkpoint").clicked() {
+                    checkpoint = Some(None);
+                }
+            });
+            ui.checkbox(&mut state.checkpoints_only, "Checkpoints only");
+            egui::ScrollArea::vertical()
+                .max_height(6.0 * TILE_SIZE)
+                .show(ui, |ui| {
+                    let history = state.course.history();
+                    for (n, depth) in history.display_order() {
+                        let node = &history.nodes()[n];
+                        let tiles = node.state.course().size();
+                        let text = match &node.checkpoint {
+                            Some(name) => format!("\u{2691} {name} ({tiles} tiles)"),
+                            None if state.checkpoints_only => continue,
+                            None => format!("{tiles} tiles"),
+                        };
+                        ui.horizontal(|ui| {
+                            ui.add_space(16.0 * depth as f32);
+                            if ui.selectable_label(n == history.pos(), text).clicked() {
+                                jump = Some(n);
+                            }
+                        });
+                    }
+                });
+        });
+    if let Some(n) = jump {
+        state.course.jump(n);
+        state.track_selection.reset_selection();
+    }
+    if let Some(name) = checkpoint {
+        state.course.set_checkpoint(name);
+    }
+    if !open {
+        state.show_history = false;
+    }
+}
+
+/// Shows the options for the race preview while it is on.
+fn draw_ghost_window(ctx: &Context, state: &mut EditState) {
+    let Some(ghost) = &mut state.ghost else {
+        return;
+    };
+    let mut open = true;
+    egui::Window::new("Race preview")
+        .open(&mut open)
+        .resizable(false)
+        .show(ctx, |ui| {
+            let last = ghost.tracker.rounds_available() - 1;
+            ui.radio_value(&mut ghost.view, GhostView::Loop, "Play in a loop");
+            ui.horizontal(|ui| {
+                let mut round = match ghost.view {
+                    GhostView::Round(r) => r,
+                    GhostView::Loop => ghost.round,
+                };
+                let showing_round = matches!(ghost.view, GhostView::Round(_));
+                if ui.radio(showing_round, "Show round").clicked()
+                    | ui.add(Slider::new(&mut round, 0..=last)).changed()
+                {
+                    ghost.view = GhostView::Round(round);
+                }
+            });
+            let status = if !ghost.has_race() {
+                "The course needs a finish to start from.".to_string()
+            } else if !ghost.is_finished() {
+                format!("Simulating... ({last} rounds)")
+            } else if ghost.tracker.is_loop_detected() {
+                format!("The cars are stuck in a loop after {last} rounds.")
+            } else {
+                format!("Finished after {last} rounds.")
+            };
+            ui.label(status);
+        });
+    if !open {
+        state.ghost = None;
+    }
+}
+
 pub fn draw_edit(
     app: &mut App,
     gfx: &mut Graphics,
@@ -442,13 +745,37 @@ pub fn draw_edit(
     if cfg!(feature = "debug") {
         process_debug_commands(app, state);
     }
+    if let Some(ghost) = &mut state.ghost {
+        ghost.update(
+            state.course.get_course(),
+            state.course.revision(),
+            state.course.get_finish().is_some(),
+            &state.level_data,
+        );
+        ghost.advance(app.timer.elapsed());
+    }
     let mut mouse_in_gui = false;
     let mut tooltip: Option<TooltipArea> = None;
     let mut draw_rect = Rect::NOTHING;
     let output = plugins.egui(|ctx| {
-        draw_goal_panel(&state.level_data, None, None, ctx);
+        let ghost = state.ghost.as_ref().filter(|g| g.has_race());
+        let seek = draw_goal_panel(
+            &state.level_data,
+            ghost.map(|g| g.tracker.get_finishes()),
+            ghost
+                .filter(|g| g.is_finished())
+                .map(|g| g.tracker.get_crashes().as_slice()),
+            ghost.and_then(|g| g.divergence.as_ref()),
+            ctx,
+        );
+        if let Some(Action::Seek(round)) = seek
+            && let Some(ghost) = &mut state.ghost
+        {
+            ghost.view = GhostView::Round(round);
+        }
         play_command = draw_playback_panel(
             PlaybackPanelState::Editing(state.course.get_finish().is_some()),
+            None,
             settings,
             ctx,
         )
@@ -464,6 +791,55 @@ pub fn draw_edit(
         if matches!(play_command, Some(Action::Keys)) {
             state.show_keys = !state.show_keys;
         }
+        let file_result = match play_command {
+            Some(Action::ImportCourse) => state.importer.open(),
+            Some(Action::ExportCourse) => export_course(state),
+            _ => Ok(()),
+        };
+        if let Err(e) = file_result {
+            state.file_error = Some(e.to_string());
+        }
+        update_file_dialogs(state, ctx);
+        match play_command {
+            Some(Action::CopyShareCode) => {
+                let code = encode(&state.level_data, state.course.get_course());
+                ctx.copy_text(code.clone());
+                state.share_dialog = Some(ShareDialog::Copied(code));
+            }
+            Some(Action::PasteShareCode) => {
+                state.share_dialog = Some(ShareDialog::Paste {
+                    code: String::new(),
+                    error: None,
+                });
+            }
+            _ => (),
+        }
+        draw_share_dialog(ctx, state);
+        if matches!(play_command, Some(Action::Slots)) {
+            if state.slots.is_some() {
+                state.slots = None;
+            } else {
+                let slots = load_slots(&state.level_data);
+                state.slot_name = slots.active_slot().name.clone();
+                state.slots = Some(slots);
+            }
+        }
+        draw_slot_window(ctx, state);
+        if matches!(play_command, Some(Action::History)) {
+            state.show_history = !state.show_history;
+        }
+        draw_history_window(ctx, state);
+        if matches!(play_command, Some(Action::GhostRace)) && state.ghost.take().is_none() {
+            state.ghost = Some(GhostRace::new(state.level_data.cars));
+        }
+        draw_ghost_window(ctx, state);
+        if matches!(play_command, Some(Action::ExportImage)) {
+            state.course_image = match state.course_image {
+                Some(_) => None,
+                None => Some(Default::default()),
+            };
+        }
+        draw_course_image_window(ctx, gfx, res, &settings.bg_color, state);
         if settings.tutorial {
             draw_tutorial(res, settings, state, ctx);
         }
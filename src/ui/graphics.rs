@@ -1,6 +1,4 @@
-use notan::draw::{
-    CreateDraw, Draw, DrawBuilder, DrawImages, DrawShapes, DrawTextSection, DrawTransform, Image,
-};
+use notan::draw::{CreateDraw, Draw, DrawImages, DrawShapes, DrawTextSection, DrawTransform};
 use notan::egui::{self, Rect, Ui};
 use notan::math::{Affine2, Mat2, Mat3, Vec2};
 use notan::prelude::*;
@@ -14,17 +12,133 @@ use crate::course::{Course, TileCoord};
 use crate::direction::{DihedralElement, Direction, rotation_for};
 use crate::playback::{CarAnimation, CarPosF};
 use crate::simulator::{CarCoord, CarData};
-use crate::tile::Tile;
+use crate::tile::{Tile, TileType};
+
+/// The drawing operations that `TileGraphics` is built on.  `Draw` draws
+/// with the GPU, while `render::SoftwareCanvas` draws into an image without
+/// a graphics context.
+pub trait Canvas {
+    type Sprite;
+    /// The sprites and fonts that are drawn with.
+    type Resources;
+
+    fn tile_sprite(res: &Self::Resources, tile_type: TileType, frame: usize) -> &Self::Sprite;
+    fn car_sprite(res: &Self::Resources, color: usize) -> &Self::Sprite;
+    fn sprite_size(sprite: &Self::Sprite) -> Vec2;
+    fn push_transform(&mut self, transform: Mat3);
+    fn pop_transform(&mut self);
+    fn clear_color(&mut self, color: Color);
+    /// Draws a sprite stretched over the rectangle from the origin to
+    /// `size`, transformed by `transform`.
+    fn draw_sprite(&mut self, sprite: &Self::Sprite, size: Vec2, transform: Mat3, alpha: f32);
+    fn fill_rect(&mut self, pos: Vec2, size: Vec2, color: Color);
+    fn draw_line(&mut self, from: Vec2, to: Vec2, width: f32, color: Color);
+    fn fill_triangle(&mut self, points: [Vec2; 3], color: Color);
+    fn fill_circle(&mut self, center: Vec2, radius: f32, color: Color);
+    fn stroke_circle(&mut self, center: Vec2, radius: f32, width: f32, color: Color);
+    /// Draws black text on a white box, centered on the origin and
+    /// transformed by `transform`.
+    fn draw_label(&mut self, res: &Self::Resources, text: &str, size: f32, transform: Mat3);
+    /// Draws text with its top left corner at `pos`.
+    fn draw_text(&mut self, res: &Self::Resources, text: &str, pos: Vec2, size: f32, color: Color);
+}
+
+impl Canvas for Draw {
+    type Sprite = Texture;
+    type Resources = Resources;
+
+    fn tile_sprite(res: &Resources, tile_type: TileType, frame: usize) -> &Texture {
+        &res.tiles[tile_type].textures[frame]
+    }
+
+    fn car_sprite(res: &Resources, color: usize) -> &Texture {
+        &res.cars[color]
+    }
+
+    fn sprite_size(sprite: &Texture) -> Vec2 {
+        Vec2::new(sprite.width(), sprite.height())
+    }
+
+    fn push_transform(&mut self, transform: Mat3) {
+        self.transform().push(transform);
+    }
+
+    fn pop_transform(&mut self) {
+        self.transform().pop();
+    }
+
+    fn clear_color(&mut self, color: Color) {
+        self.clear(color);
+    }
+
+    fn draw_sprite(&mut self, sprite: &Texture, size: Vec2, transform: Mat3, alpha: f32) {
+        self.image(sprite)
+            .size(size.x, size.y)
+            .transform(transform)
+            .alpha(alpha);
+    }
+
+    fn fill_rect(&mut self, pos: Vec2, size: Vec2, color: Color) {
+        self.rect((pos.x, pos.y), (size.x, size.y)).color(color);
+    }
 
-pub struct TileGraphics<'a> {
-    pub res: &'a Resources,
+    fn draw_line(&mut self, from: Vec2, to: Vec2, width: f32, color: Color) {
+        self.line((from.x, from.y), (to.x, to.y))
+            .color(color)
+            .width(width);
+    }
+
+    fn fill_triangle(&mut self, [a, b, c]: [Vec2; 3], color: Color) {
+        self.triangle((a.x, a.y), (b.x, b.y), (c.x, c.y))
+            .color(color);
+    }
+
+    fn fill_circle(&mut self, center: Vec2, radius: f32, color: Color) {
+        self.circle(radius)
+            .position(center.x, center.y)
+            .color(color);
+    }
+
+    fn stroke_circle(&mut self, center: Vec2, radius: f32, width: f32, color: Color) {
+        self.circle(radius)
+            .position(center.x, center.y)
+            .stroke_color(color)
+            .stroke(width);
+    }
+
+    fn draw_label(&mut self, res: &Resources, text: &str, size: f32, transform: Mat3) {
+        let draw_text = |draw: &mut Self| {
+            draw.text(&res.font, text)
+                .transform(transform)
+                .color(Color::BLACK)
+                .size(size)
+                .h_align_center()
+                .v_align_middle();
+        };
+        draw_text(self);
+        let bound = self.last_text_bounds();
+        self.rect((bound.x, bound.y), (bound.width, bound.height))
+            .transform(transform);
+        draw_text(self);
+    }
+
+    fn draw_text(&mut self, res: &Resources, text: &str, pos: Vec2, size: f32, color: Color) {
+        self.text(&res.font, text)
+            .position(pos.x, pos.y)
+            .size(size)
+            .color(color);
+    }
+}
+
+pub struct TileGraphics<'a, D: Canvas = Draw> {
+    pub res: &'a D::Resources,
     pub zoom: f32,
     pub bg_color: &'a [f32; 3],
-    pub draw: Draw,
+    pub draw: D,
     pub round: usize,
 }
 
-impl TileGraphics<'_> {
+impl<D: Canvas> TileGraphics<'_, D> {
     pub fn tile_size(&self) -> f32 {
         self.zoom * TILE_SIZE
     }
@@ -75,27 +189,31 @@ impl TileGraphics<'_> {
         Affine2::from_mat2_translation(rot, translation + rot * offset).into()
     }
 
-    pub fn draw_tile(&mut self, tile: Tile, pos: TileCoord) -> DrawBuilder<'_, Image<'_>> {
+    pub fn draw_tile(&mut self, tile: Tile, pos: TileCoord) {
+        self.draw_tile_with_alpha(tile, pos, 1.0);
+    }
+
+    pub fn draw_tile_with_alpha(&mut self, tile: Tile, pos: TileCoord, alpha: f32) {
         let total_offset = (self.round ^ (tile.offset as usize) ^ 1) & 1;
-        let sprite = &self.res.tiles[tile.tile_type].textures[total_offset];
-        self.draw_tile_sprite(sprite, tile.transform, pos)
+        let sprite = D::tile_sprite(self.res, tile.tile_type, total_offset);
+        self.draw_tile_sprite(sprite, tile.transform, pos, alpha);
     }
 
-    pub fn draw_tile_sprite<'a>(
+    pub fn draw_tile_sprite(
         &mut self,
-        sprite: &'a Texture,
+        sprite: &D::Sprite,
         trans: DihedralElement,
         pos: TileCoord,
-    ) -> DrawBuilder<'_, Image<'a>> {
+        alpha: f32,
+    ) {
         let tsz = self.tile_size();
         let trans = self.transform_for(trans, pos);
-        let mut builder = self.draw.image(sprite);
-        builder.size(tsz, tsz).transform(trans);
-        builder
+        self.draw
+            .draw_sprite(sprite, Vec2::new(tsz, tsz), trans, alpha);
     }
 
     pub fn draw_course(&mut self, course: &Course) {
-        self.draw.clear(Color::from_rgb(
+        self.draw.clear_color(Color::from_rgb(
             self.bg_color[0],
             self.bg_color[1],
             self.bg_color[2],
@@ -106,32 +224,40 @@ impl TileGraphics<'_> {
     }
 
     pub fn set_offset(&mut self, offset: &Vec2) {
-        self.draw.transform().push(Mat3::from_translation(*offset));
+        self.draw.push_transform(Mat3::from_translation(*offset));
     }
 
-    pub fn draw_highlights<'a>(&mut self, tiles: impl IntoIterator<Item = &'a TileCoord>) {
+    pub fn draw_highlights<'b>(&mut self, tiles: impl IntoIterator<Item = &'b TileCoord>) {
         let tsz = self.tile_size();
         for pos in tiles {
-            let screen_pos = ((pos.0 as f32) * tsz, (pos.1 as f32) * tsz);
-            self.draw.rect(screen_pos, (tsz, tsz)).color(Color::TEAL);
+            let screen_pos = self.tile_ul_to_screen(*pos);
+            self.draw
+                .fill_rect(screen_pos, Vec2::new(tsz, tsz), Color::TEAL);
         }
     }
 
+    fn car_size(&self, sprite: &D::Sprite) -> Vec2 {
+        D::sprite_size(sprite) * CAR_SCALE_RATIO * self.zoom
+    }
+
     pub fn draw_car(&mut self, car: &CarData) {
         let rot = rotation_for(Direction::Up, car.dir);
-        let sprite = &self.res.cars[color_for_car(car.id)];
-        let width = sprite.width() * CAR_SCALE_RATIO * self.zoom;
-        let height = sprite.height() * CAR_SCALE_RATIO * self.zoom;
-        let trans = self.transform_for_car(rot, car.pos, width, height);
-        self.draw.image(sprite).size(width, height).transform(trans);
+        let sprite = D::car_sprite(self.res, color_for_car(car.id));
+        let size = self.car_size(sprite);
+        let trans = self.transform_for_car(rot, car.pos, size.x, size.y);
+        self.draw.draw_sprite(sprite, size, trans, 1.0);
     }
 
     pub fn draw_car_smooth(&mut self, id: usize, pos: &CarPosF) {
-        let sprite = &self.res.cars[color_for_car(id)];
-        let width = sprite.width() * CAR_SCALE_RATIO * self.zoom;
-        let height = sprite.height() * CAR_SCALE_RATIO * self.zoom;
-        let trans = self.transform_for_car_smooth(pos, width, height);
-        self.draw.image(sprite).size(width, height).transform(trans);
+        self.draw_ghost_car(id, pos, 1.0);
+    }
+
+    /// Draws a translucent car, for previewing a race over the course.
+    pub fn draw_ghost_car(&mut self, id: usize, pos: &CarPosF, alpha: f32) {
+        let sprite = D::car_sprite(self.res, color_for_car(id));
+        let size = self.car_size(sprite);
+        let trans = self.transform_for_car_smooth(pos, size.x, size.y);
+        self.draw.draw_sprite(sprite, size, trans, alpha);
     }
 
     pub fn draw_cars(&mut self, cars: &[CarData], animations: &[CarAnimation], t: f32) {
@@ -147,25 +273,9 @@ impl TileGraphics<'_> {
         }
     }
 
-    fn draw_number(&mut self, text: &str, mat: Mat3) {
-        let size = self.tile_size() * 0.4;
-        self.draw
-            .text(&self.res.font, text)
-            .transform(mat)
-            .color(Color::BLACK)
-            .size(size)
-            .h_align_center()
-            .v_align_middle();
-    }
-
     fn draw_car_number_base(&mut self, id: usize, mat: Mat3) {
-        let text = id.to_string();
-        self.draw_number(&text, mat);
-        let bound = self.draw.last_text_bounds();
-        self.draw
-            .rect((bound.x, bound.y), (bound.width, bound.height))
-            .transform(mat);
-        self.draw_number(&text, mat);
+        let size = self.tile_size() * 0.4;
+        self.draw.draw_label(self.res, &id.to_string(), size, mat);
     }
 
     pub fn draw_car_number(&mut self, car: &CarData) {
@@ -193,9 +303,7 @@ impl TileGraphics<'_> {
         };
         let p1 = self.car_to_screen(pos + d);
         let p2 = self.car_to_screen(pos + d.opposite());
-        self.draw
-            .line((p1.x, p1.y), (p2.x, p2.y))
-            .color(Color::BLACK);
+        self.draw.draw_line(p1, p2, 1.0, Color::BLACK);
     }
 
     fn draw_tile_boundary_arrow_car_coord(&mut self, pos: CarCoord, adj: CarCoord) {
@@ -205,9 +313,7 @@ impl TileGraphics<'_> {
         let t1 = base - 0.2 * out;
         let t2 = base - 0.05 * out + 0.25 * rot;
         let t3 = base - 0.05 * out - 0.25 * rot;
-        self.draw
-            .triangle((t1.x, t1.y), (t2.x, t2.y), (t3.x, t3.y))
-            .color(Color::BLACK);
+        self.draw.fill_triangle([t1, t2, t3], Color::BLACK);
     }
 
     pub fn draw_tile_boundary_arrow(&mut self, pos: CarCoord, adj: TileCoord) {
@@ -225,8 +331,91 @@ impl TileGraphics<'_> {
         self.draw_tile_boundary_arrow_car_coord(pos, pos + d);
         self.draw_tile_boundary_arrow_car_coord(pos, pos + d.opposite());
     }
+
+    /// Shades each tile of the course by its share of the largest value.
+    /// Tiles with a value of zero are tinted blue.
+    pub fn draw_heatmap(&mut self, course: &Course, value: impl Fn(TileCoord) -> usize) {
+        let tsz = self.tile_size();
+        let max = course.keys().map(|&pos| value(pos)).max().unwrap_or(0);
+        for &pos in course.keys() {
+            let v = value(pos);
+            let color = if v == 0 {
+                Color::new(0.2, 0.3, 1.0, 0.3)
+            } else {
+                Color::new(1.0, 0.1, 0.0, 0.15 + 0.55 * (v as f32) / (max as f32))
+            };
+            let ul = self.tile_ul_to_screen(pos);
+            self.draw.fill_rect(ul, Vec2::new(tsz, tsz), color);
+            if v > 0 {
+                let text_pos = ul + Vec2::new(0.06 * tsz, 0.04 * tsz);
+                self.draw
+                    .draw_text(self.res, &v.to_string(), text_pos, 0.22 * tsz, Color::BLACK);
+            }
+        }
+    }
+
+    /// Draws the outline of the rectangle of tiles from `tl` to `br`.
+    pub fn draw_tile_rect(&mut self, tl: TileCoord, br: TileCoord) {
+        let min = self.tile_ul_to_screen(tl);
+        let max = self.tile_ul_to_screen(TileCoord(br.0 + 1, br.1 + 1));
+        let width = 0.05 * self.tile_size();
+        let corners = [min, Vec2::new(max.x, min.y), max, Vec2::new(min.x, max.y)];
+        for (i, &corner) in corners.iter().enumerate() {
+            self.draw
+                .draw_line(corner, corners[(i + 1) % 4], width, Color::MAGENTA);
+        }
+    }
+
+    /// Draws a ring around a car to pick it out from the others.
+    pub fn draw_car_highlight(&mut self, pos: Vec2) {
+        let center = self.car_to_screen_smooth(pos);
+        let tsz = self.tile_size();
+        self.draw
+            .stroke_circle(center, 0.45 * tsz, 0.06 * tsz, Color::MAGENTA);
+    }
+
+    /// Draws a line through the recent positions of a car, fading out
+    /// towards the oldest position.
+    pub fn draw_trail(&mut self, id: usize, positions: &[CarCoord]) {
+        let [r, g, b] = TRAIL_COLORS[color_for_car(id)];
+        let width = 0.08 * self.tile_size();
+        let n = positions.len();
+        for (i, pair) in positions.windows(2).enumerate() {
+            let p1 = self.car_to_screen(pair[0]);
+            let p2 = self.car_to_screen(pair[1]);
+            let alpha = 0.7 * ((i + 1) as f32) / ((n - 1) as f32);
+            self.draw
+                .draw_line(p1, p2, width, Color::new(r, g, b, alpha));
+        }
+    }
+
+    /// Draws the path that a car will take, ending in a dot where it
+    /// leaves the course.
+    pub fn draw_route(&mut self, id: usize, positions: &[CarCoord]) {
+        let [r, g, b] = TRAIL_COLORS[color_for_car(id)];
+        let color = Color::new(r, g, b, 0.5);
+        let width = 0.05 * self.tile_size();
+        for pair in positions.windows(2) {
+            let p1 = self.car_to_screen(pair[0]);
+            let p2 = self.car_to_screen(pair[1]);
+            self.draw.draw_line(p1, p2, width, color);
+        }
+        if let Some(&last) = positions.last() {
+            let end = self.car_to_screen(last);
+            self.draw.fill_circle(end, 1.5 * width, color);
+        }
+    }
 }
 
+/// The colors of the car sprites, in the order returned by `color_for_car`.
+static TRAIL_COLORS: [[f32; 3]; 5] = [
+    [0.2, 0.2, 0.2],
+    [0.1, 0.4, 0.9],
+    [0.1, 0.7, 0.2],
+    [0.9, 0.1, 0.1],
+    [0.95, 0.8, 0.1],
+];
+
 impl From<CarCoord> for Vec2 {
     fn from(value: CarCoord) -> Self {
         0.5 * TILE_SIZE * Self::new(value.0 as f32, value.1 as f32)
@@ -1,49 +1,340 @@
 use super::gui::central_panel;
-use super::loader::Resources;
+use super::settings::Settings;
+use crate::level::{LevelData, SolveData};
+use crate::metrics::{Metric, summary};
+use crate::pack::{install_pack, levels_to_toml};
+use crate::records::{Medal, best_courses, par_comparison};
+use crate::replay_file::ReplayFile;
 use crate::states::{SelectState, SelectStatus};
 use notan::app::{App, Color, Graphics, Plugins};
-use notan::egui::{self, EguiPluginSugar};
+use notan::egui::{self, Color32, Context, EguiPluginSugar, Ui};
+
+fn pack_selector(ui: &mut Ui, state: &mut SelectState) {
+    let mut selected = state.current_pack;
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_label("Level pack")
+            .selected_text(&state.current().pack.meta.name)
+            .show_ui(ui, |ui| {
+                for (n, progress) in state.packs.iter().enumerate() {
+                    let text = format!(
+                        "{} ({}/{})",
+                        progress.pack.meta.name,
+                        progress.num_solved(),
+                        progress.pack.levels.len()
+                    );
+                    ui.selectable_value(&mut selected, n, text);
+                }
+            });
+        if ui.button("Import pack").clicked()
+            && let Err(e) = state.importer.open()
+        {
+            state.import_status = Some(format!("Import failed: {e}"));
+        }
+    });
+    if selected != state.current_pack {
+        state.select_pack(selected);
+    }
+    let meta = &state.current().pack.meta;
+    let mut info = Vec::new();
+    if !meta.author.is_empty() {
+        info.push(format!("By {}", meta.author));
+    }
+    if !meta.version.is_empty() {
+        info.push(format!("Version {}", meta.version));
+    }
+    if !info.is_empty() {
+        ui.label(info.join(", "));
+    }
+    if let Some(status) = &state.import_status {
+        ui.label(status);
+    }
+    if !state.pack_errors.is_empty() {
+        ui.collapsing("Some level packs could not be loaded", |ui| {
+            for e in &state.pack_errors {
+                ui.colored_label(Color32::RED, e);
+            }
+        });
+    }
+}
+
+fn record_headings(ui: &mut Ui, metric: Metric) {
+    ui.heading("Level");
+    ui.heading(metric.name())
+        .on_hover_text(metric.description());
+    ui.heading("Rounds");
+    ui.heading("Par");
+    ui.heading("Medal");
+    ui.end_row();
+}
+
+/// Shows the best solve of a level and how it compares to par.  Returns true
+/// if the run history button was clicked.
+fn record_cells(ui: &mut Ui, lvl: &LevelData, solve: Option<SolveData>, metric: Metric) -> bool {
+    match solve {
+        Some(solve) => {
+            ui.label(metric.value_text(&solve))
+                .on_hover_text(summary(&solve));
+            ui.label(solve.turns.to_string());
+        }
+        None => {
+            ui.label("");
+            ui.label("");
+        }
+    }
+    match lvl.par {
+        Some(par) => {
+            ui.label(format!("{} / {}", metric.value_text(&par), par.turns))
+                .on_hover_text(format!("{} / rounds", metric.name()));
+        }
+        None => {
+            ui.label("");
+        }
+    }
+    match (solve, lvl.par) {
+        (Some(solve), Some(par)) => {
+            let text = Medal::for_solve(&solve, &par, metric).map_or("", Medal::icon);
+            ui.label(text)
+                .on_hover_text(par_comparison(&solve, &par, metric));
+        }
+        _ => {
+            ui.label("");
+        }
+    }
+    solve.is_some() && ui.button("\u{1f4c8}").on_hover_text("Records").clicked()
+}
+
+/// Shows the run history and Pareto-optimal courses of a level.  Returns the
+/// course to open, if the player chose one.
+fn draw_records(ctx: &Context, state: &mut SelectState, metric: Metric) -> Option<SelectStatus> {
+    let records = state.records.as_ref()?;
+    let mut open = true;
+    let mut selection = None;
+    egui::Window::new(format!("Records: {}", records.level.name))
+        .open(&mut open)
+        .show(ctx, |ui| {
+            if !records.pareto.is_empty() {
+                ui.label("Best courses");
+                egui::Grid::new("Pareto grid").show(ui, |ui| {
+                    ui.heading(metric.name());
+                    ui.heading("Rounds");
+                    ui.end_row();
+                    for entry in best_courses(&records.pareto, metric) {
+                        ui.label(metric.value_text(&entry.solve))
+                            .on_hover_text(summary(&entry.solve));
+                        ui.label(entry.solve.turns.to_string());
+                        if ui.button("Open").clicked() {
+                            selection = Some(SelectStatus::LevelWithCourse(
+                                records.level.clone(),
+                                entry.course.clone(),
+                            ));
+                        }
+                        ui.end_row();
+                    }
+                });
+                ui.separator();
+            }
+            ui.label("Run history");
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                egui::Grid::new("Run history grid").show(ui, |ui| {
+                    ui.heading("Date");
+                    ui.heading(metric.name());
+                    ui.heading("Rounds");
+                    ui.end_row();
+                    for run in records.runs.iter().rev() {
+                        ui.label(run.date_string());
+                        ui.label(metric.value_text(&run.solve))
+                            .on_hover_text(summary(&run.solve));
+                        ui.label(run.solve.turns.to_string());
+                        ui.end_row();
+                    }
+                });
+            });
+        });
+    if !open {
+        state.records = None;
+    }
+    selection
+}
+
+fn export_custom_level(state: &mut SelectState, n: usize) {
+    let text = levels_to_toml(&[state.custom.pack.levels[n].as_ref().clone()]);
+    let result = state.exporter.set_save_action(
+        Box::new(move |w| {
+            w.write_all(text.as_bytes())?;
+            Ok(())
+        }),
+        "level.toml",
+    );
+    if let Err(e) = result {
+        state.import_status = Some(format!("Export failed: {e}"));
+    }
+}
+
+fn custom_levels(ui: &mut Ui, state: &mut SelectState, metric: Metric) -> SelectStatus {
+    let mut selection = SelectStatus::Idle;
+    let mut rename = None;
+    let mut remove = None;
+    let mut export = None;
+    let mut history = None;
+    egui::Grid::new("Custom grid").show(ui, |ui| {
+        record_headings(ui, metric);
+        for (n, lev) in state.custom.pack.levels.iter().enumerate() {
+            if let Some((m, name)) = &mut state.renaming
+                && *m == n
+            {
+                ui.text_edit_singleline(name);
+                if ui.button("Ok").clicked() && !name.trim().is_empty() {
+                    rename = Some((n, name.trim().to_string()));
+                }
+                if ui.button("Cancel").clicked() {
+                    state.renaming = None;
+                }
+                ui.end_row();
+                continue;
+            }
+            let check = if state.custom.solved[n].is_some() {
+                " \u{2714}"
+            } else {
+                ""
+            };
+            if ui.button(format!("{}{check}", &lev.name)).clicked() {
+                selection = SelectStatus::Level(lev.clone());
+            }
+            if record_cells(ui, lev, state.custom.best_solve(n, metric), metric) {
+                history = Some(lev.clone());
+            }
+            if ui.button("Rename").clicked() {
+                state.renaming = Some((n, lev.name.clone()));
+            }
+            if ui.button("Duplicate").clicked() {
+                let mut copy = lev.as_ref().clone();
+                copy.name = format!("{} (copy)", copy.name);
+                selection = SelectStatus::Design(copy);
+            }
+            if ui.button("Export").clicked() {
+                export = Some(n);
+            }
+            if ui.button("\u{1f5d1}").on_hover_text("Delete").clicked() {
+                remove = Some(n);
+            }
+            ui.end_row();
+        }
+    });
+    if let Some(lvl) = history {
+        state.show_records(&lvl);
+    }
+    if let Some((n, name)) = rename {
+        state.rename_custom(n, &name);
+        state.renaming = None;
+    }
+    if let Some(n) = export {
+        export_custom_level(state, n);
+    }
+    if let Some(n) = remove {
+        state.remove_custom(n);
+        state.renaming = None;
+    }
+    selection
+}
+
+fn update_replay_import(ctx: &Context, state: &mut SelectState) -> Option<SelectStatus> {
+    let result = state
+        .replay_importer
+        .update(ctx)
+        .and_then(|data| data.map(|bytes| ReplayFile::from_bytes(&bytes)).transpose());
+    match result {
+        Ok(replay) => replay.map(|r| SelectStatus::Replay(Box::new(r))),
+        Err(e) => {
+            state.import_status = Some(format!("Import failed: {e}"));
+            None
+        }
+    }
+}
+
+fn update_pack_import(ctx: &Context, state: &mut SelectState) {
+    let result = state.importer.update(ctx).and_then(|data| match data {
+        Some(bytes) => Ok(Some(String::from_utf8(bytes)?)),
+        None => Ok(None),
+    });
+    match result {
+        Ok(Some(text)) => match install_pack(&text) {
+            Ok(meta) => {
+                state.reload(&meta.name);
+                state.import_status = Some(format!("Imported \"{}\"", meta.name));
+            }
+            Err(e) => state.import_status = Some(format!("Import failed: {e}")),
+        },
+        Ok(None) => (),
+        Err(e) => state.import_status = Some(format!("Import failed: {e}")),
+    }
+}
 
 pub fn draw_home_screen(
     app: &mut App,
     gfx: &mut Graphics,
     plugins: &mut Plugins,
-    res: &Resources,
-    state: &SelectState,
+    settings: &Settings,
+    state: &mut SelectState,
 ) -> SelectStatus {
+    let metric = settings.metric;
     let mut selection = SelectStatus::Idle;
-    let num_solved = state.solved.iter().filter(|s| s.is_some()).count();
     let mut output = plugins.egui(|ctx| {
+        update_pack_import(ctx, state);
+        if let Some(status) = update_replay_import(ctx, state) {
+            selection = status;
+        }
+        if let Some(status) = draw_records(ctx, state, metric) {
+            selection = status;
+        }
+        if let Err(e) = state.exporter.update(ctx) {
+            state.import_status = Some(format!("Export failed: {e}"));
+        }
         central_panel(ctx, egui::Align::Min, |ui| {
             ui.heading("Select a level");
+            pack_selector(ui, state);
+            let progress = state.current();
+            let num_solved = progress.num_solved();
+            let mut history = None;
             egui::Grid::new("Select grid").show(ui, |ui| {
-                ui.heading("Level");
-                ui.heading("Area");
-                ui.heading("Rounds");
-                ui.end_row();
-                for (n, lev) in res.levels.iter().enumerate() {
+                record_headings(ui, metric);
+                for (n, lev) in progress.pack.levels.iter().enumerate() {
                     if n > 2 * num_solved {
                         break;
                     }
-                    let check = if state.solved[n].is_some() {
+                    let check = if progress.solved[n].is_some() {
                         " \u{2714}"
                     } else {
                         ""
                     };
                     let display = format!("{}{check}", &lev.name);
                     if ui.button(&display).clicked() {
-                        selection = SelectStatus::Level(n);
+                        selection = SelectStatus::Level(lev.clone());
                     }
-                    if let Some(solve) = state.solved[n] {
-                        ui.label(solve.tiles.to_string());
-                        ui.label(solve.turns.to_string());
+                    if record_cells(ui, lev, progress.best_solve(n, metric), metric) {
+                        history = Some(lev.clone());
                     }
                     ui.end_row();
                 }
             });
+            if let Some(lvl) = history {
+                state.show_records(&lvl);
+            }
             if ui.button("Custom level").clicked() {
                 selection = SelectStatus::Custom;
             }
+            if ui.button("Open replay").clicked()
+                && let Err(e) = state.replay_importer.open()
+            {
+                state.import_status = Some(format!("Import failed: {e}"));
+            }
+            if !state.custom.pack.levels.is_empty() {
+                ui.collapsing("My custom levels", |ui| {
+                    let status = custom_levels(ui, state, metric);
+                    if !matches!(status, SelectStatus::Idle) {
+                        selection = status;
+                    }
+                });
+            }
             ui.separator();
             if ui.button("\u{2699} Settings").clicked() {
                 selection = SelectStatus::Settings;
@@ -0,0 +1,123 @@
+use notan::egui::Context;
+
+pub trait FileImport {
+    fn open(&mut self) -> Result<(), anyhow::Error>;
+    fn update(&mut self, ctx: &Context) -> Result<Option<Vec<u8>>, anyhow::Error>;
+}
+
+#[cfg(target_arch = "wasm32")]
+pub use web::make_importer;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use native::make_importer;
+
+#[cfg(target_arch = "wasm32")]
+mod web {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::{Context, FileImport};
+    use anyhow::anyhow;
+    use wasm_bindgen::{JsCast, JsValue, closure::Closure};
+
+    type Loaded = Rc<RefCell<Option<Result<Vec<u8>, String>>>>;
+
+    struct Import {
+        loaded: Loaded,
+    }
+
+    pub fn make_importer() -> Box<dyn FileImport> {
+        Box::new(Import {
+            loaded: Default::default(),
+        })
+    }
+
+    fn jsv(v: JsValue) -> anyhow::Error {
+        anyhow!("{:?}", v)
+    }
+
+    fn np() -> anyhow::Error {
+        anyhow!("error in javascript code")
+    }
+
+    fn read_file(file: &web_sys::File, loaded: Loaded) -> Result<(), JsValue> {
+        let reader = web_sys::FileReader::new()?;
+        let reader_cb = reader.clone();
+        let onload = Closure::<dyn FnMut()>::once(move || {
+            let result = reader_cb
+                .result()
+                .map(|buf| js_sys::Uint8Array::new(&buf).to_vec())
+                .map_err(|e| format!("{:?}", e));
+            *loaded.borrow_mut() = Some(result);
+        });
+        reader.set_onload(Some(onload.as_ref().unchecked_ref()));
+        onload.forget();
+        reader.read_as_array_buffer(file)
+    }
+
+    impl FileImport for Import {
+        fn open(&mut self) -> Result<(), anyhow::Error> {
+            let doc = web_sys::window()
+                .ok_or_else(np)?
+                .document()
+                .ok_or_else(np)?;
+            let input: web_sys::HtmlInputElement = doc
+                .create_element("input")
+                .map_err(jsv)?
+                .dyn_into()
+                .map_err(|_| np())?;
+            input.set_type("file");
+            let input_cb = input.clone();
+            let loaded = self.loaded.clone();
+            let onchange = Closure::<dyn FnMut()>::once(move || {
+                if let Some(file) = input_cb.files().and_then(|f| f.get(0))
+                    && let Err(e) = read_file(&file, loaded.clone())
+                {
+                    *loaded.borrow_mut() = Some(Err(format!("{:?}", e)));
+                }
+            });
+            input.set_onchange(Some(onchange.as_ref().unchecked_ref()));
+            onchange.forget();
+            input.click();
+            Ok(())
+        }
+
+        fn update(&mut self, _ctx: &Context) -> Result<Option<Vec<u8>>, anyhow::Error> {
+            match self.loaded.borrow_mut().take() {
+                Some(Ok(data)) => Ok(Some(data)),
+                Some(Err(e)) => Err(anyhow!(e)),
+                None => Ok(None),
+            }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+    use egui_file_dialog::FileDialog;
+
+    use super::{Context, FileImport};
+
+    pub struct Import {
+        dialog: FileDialog,
+    }
+
+    pub fn make_importer() -> Box<dyn FileImport> {
+        Box::new(Import {
+            dialog: FileDialog::new(),
+        })
+    }
+
+    impl FileImport for Import {
+        fn open(&mut self) -> Result<(), anyhow::Error> {
+            self.dialog.pick_file();
+            Ok(())
+        }
+
+        fn update(&mut self, ctx: &Context) -> Result<Option<Vec<u8>>, anyhow::Error> {
+            match self.dialog.update(ctx).take_picked() {
+                Some(path) => Ok(Some(std::fs::read(path)?)),
+                None => Ok(None),
+            }
+        }
+    }
+}
@@ -13,6 +13,11 @@ pub fn mouse_coords(app: &App, settings: &Settings, offset: &Vec2) -> TileCoord
     TileCoord(x, y)
 }
 
+/// The position of the mouse in tile units.
+pub fn mouse_pos(app: &App, settings: &Settings, offset: &Vec2) -> Vec2 {
+    (Vec2::new(app.mouse.x, app.mouse.y) - *offset) / settings.tile_size()
+}
+
 pub fn mouse_coords_car(app: &App, settings: &Settings, offset: &Vec2) -> Option<CarCoord> {
     let tsz = settings.tile_size();
     let xf = (app.mouse.x - offset.x) / tsz;
@@ -35,6 +40,10 @@ pub fn key_name(code: KeyCode) -> String {
         KeyCode::Escape => "Esc".to_string(),
         KeyCode::Comma => ",".to_string(),
         KeyCode::Period => ".".to_string(),
+        KeyCode::BracketLeft => "[".to_string(),
+        KeyCode::BracketRight => "]".to_string(),
+        KeyCode::Minus => "-".to_string(),
+        KeyCode::Equal => "=".to_string(),
         _ => {
             let name = format!("{:?}", code);
             name.trim_start_matches("Arrow")
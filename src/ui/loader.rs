@@ -3,10 +3,11 @@ use notan::app::Texture;
 use notan::draw::{CreateFont, Font};
 use notan::egui::{EguiRegisterTexture, FontDefinitions, FontFamily};
 use notan::prelude::Graphics;
-use serde::Deserialize;
+use std::borrow::Cow;
 use std::rc::Rc;
 
 use crate::level::LevelData;
+use crate::pack::builtin_pack;
 use crate::tile::TileType;
 
 pub type GuiImage = notan::egui::SizedTexture;
@@ -57,17 +58,11 @@ impl Resources {
     }
 }
 
-#[derive(Deserialize)]
-struct Levels {
-    levels: Vec<Rc<LevelData>>,
-}
-
 pub fn load_levels() -> Vec<Rc<LevelData>> {
-    let l: Levels = toml::from_str(include_str!("../../res/levels.toml")).unwrap();
-    l.levels
+    builtin_pack().levels
 }
 
-static CAR_IMAGES: &[&[u8]] = &[
+pub static CAR_IMAGES: &[&[u8]] = &[
     include_bytes!("../../res/car_black_1.png"),
     include_bytes!("../../res/car_blue_1.png"),
     include_bytes!("../../res/car_green_1.png"),
@@ -128,9 +123,13 @@ pub fn load_cars(gfx: &mut Graphics) -> Vec<Texture> {
     CAR_IMAGES.iter().map(|i| load_texture(gfx, i)).collect()
 }
 
-pub fn load_font(gfx: &mut Graphics) -> Font {
+/// The data of egui's default proportional font.
+pub fn font_bytes() -> Cow<'static, [u8]> {
     let fonts = FontDefinitions::default();
     let font_name = &fonts.families[&FontFamily::Proportional][0];
-    let font = &fonts.font_data[font_name];
-    gfx.create_font(&font.font).unwrap()
+    fonts.font_data[font_name].font.clone()
+}
+
+pub fn load_font(gfx: &mut Graphics) -> Font {
+    gfx.create_font(&font_bytes()).unwrap()
 }
@@ -1,16 +1,21 @@
+use enum_map::EnumMap;
 use notan::{
     app::{App, Color, Graphics, Plugins},
-    egui::{self, Context, EguiPluginSugar, Grid, Rgba, RichText, Ui},
+    egui::{self, Color32, Context, EguiPluginSugar, Grid, Rgba, RichText, Ui},
 };
+use strum::IntoEnumIterator;
 
 use super::{
     graphics::TILE_SIZE, gui::central_panel, input::key_name, loader::Resources, settings::Settings,
 };
 use crate::{
     input::Action,
-    level::LevelData,
-    save::save_or_log_err,
-    states::{CustomSpecState, DialogResponse, SettingsMenu, SettingsState},
+    level::{LevelData, MAX_CARS},
+    metrics::Metric,
+    pack::levels_to_toml,
+    save::{ImportPolicy, Profile, save_or_log_err},
+    states::{CustomSpecState, DialogResponse, SelectState, SettingsMenu, SettingsState},
+    tile::TileType,
 };
 
 pub fn apply_zoom_settings(settings: &Settings, ctx: &Context) {
@@ -33,6 +38,19 @@ fn size_combo(ui: &mut Ui, name: &str, value: &mut f32) {
         });
 }
 
+fn metric_combo(ui: &mut Ui, metric: &mut Metric) {
+    egui::ComboBox::from_label("Metric to optimize")
+        .selected_text(metric.name())
+        .show_ui(ui, |ui| {
+            for m in Metric::iter() {
+                ui.selectable_value(metric, m, m.name())
+                    .on_hover_text(m.description());
+            }
+        })
+        .response
+        .on_hover_text("Records and best courses compare this together with rounds");
+}
+
 fn display_settings(app: &mut App, res: &Resources, settings: &mut Settings, ui: &mut Ui) {
     let old_font_size = settings.zoom.font_size;
     ui.checkbox(&mut settings.smooth_animation, "Smooth animation");
@@ -51,6 +69,7 @@ fn display_settings(app: &mut App, res: &Resources, settings: &mut Settings, ui:
     }
     size_combo(ui, "Road tile size", &mut settings.zoom.tile_size);
     size_combo(ui, "UI size", &mut settings.zoom.font_size);
+    metric_combo(ui, &mut settings.metric);
     apply_zoom_settings(settings, ui.ctx());
     /*
     ui.add(
@@ -95,6 +114,13 @@ pub fn settings_menu(
             SettingsMenu::ChooseKey(a) => Some(settings_menu_choose_key(app, settings, ctx, a)),
             SettingsMenu::Display => Some(settings_menu_display(app, res, settings, ctx)),
             SettingsMenu::Help => Some(settings_menu_help(settings, ctx)),
+            SettingsMenu::Profile => Some(settings_menu_profile(settings, state, ctx)),
+        };
+        let _ = state.exporter.update(ctx);
+        match state.importer.update(ctx) {
+            Ok(Some(data)) => import_profile(res, settings, state, ctx, &data),
+            Ok(None) => (),
+            Err(e) => state.profile_status = Some(format!("Import failed: {e}")),
         }
     });
     output.clear_color(Color::BLACK);
@@ -122,6 +148,9 @@ pub fn settings_menu_main(ctx: &Context) -> Option<SettingsMenu> {
         if ui.button(RichText::new("Help").heading()).clicked() {
             menu = Some(SettingsMenu::Help);
         }
+        if ui.button(RichText::new("Profile").heading()).clicked() {
+            menu = Some(SettingsMenu::Profile);
+        }
         ui.add_space(20.0);
         if ui.button(RichText::new("Close").heading()).clicked() {
             menu = None;
@@ -166,6 +195,85 @@ pub fn settings_menu_help(settings: &mut Settings, ctx: &Context) -> SettingsMen
     })
 }
 
+static IMPORT_POLICIES: [ImportPolicy; 3] = [
+    ImportPolicy::KeepBest,
+    ImportPolicy::Overwrite,
+    ImportPolicy::Skip,
+];
+
+pub fn settings_menu_profile(
+    settings: &Settings,
+    state: &mut SettingsState,
+    ctx: &Context,
+) -> SettingsMenu {
+    central_panel(ctx, egui::Align::Min, |ui| {
+        ui.heading("Profile");
+        ui.label("A profile holds your saved courses, solves, custom levels and settings.");
+        ui.add_space(20.0);
+        if ui
+            .button(RichText::new("Export profile").heading())
+            .clicked()
+        {
+            let result = Profile::collect(settings).to_bytes().and_then(|bytes| {
+                state.exporter.set_save_action(
+                    Box::new(move |w| {
+                        w.write_all(&bytes)?;
+                        Ok(())
+                    }),
+                    "race.profile",
+                )
+            });
+            state.profile_status = result.err().map(|e| format!("Export failed: {e}"));
+        }
+        ui.add_space(20.0);
+        ui.label("When a level already has saved data:");
+        for policy in IMPORT_POLICIES {
+            ui.radio_value(&mut state.import_policy, policy, policy.name());
+        }
+        ui.checkbox(&mut state.import_settings, "Import settings");
+        if ui
+            .button(RichText::new("Import profile").heading())
+            .clicked()
+            && let Err(e) = state.importer.open()
+        {
+            state.profile_status = Some(format!("Import failed: {e}"));
+        }
+        if let Some(status) = &state.profile_status {
+            ui.add_space(20.0);
+            ui.label(status);
+        }
+        ui.add_space(20.0);
+        if ui.button(RichText::new("Back").heading()).clicked() {
+            SettingsMenu::Main
+        } else {
+            SettingsMenu::Profile
+        }
+    })
+}
+
+fn import_profile(
+    res: &Resources,
+    settings: &mut Settings,
+    state: &mut SettingsState,
+    ctx: &Context,
+    data: &[u8],
+) {
+    match Profile::from_bytes(data) {
+        Ok(profile) => {
+            let count = profile.apply(state.import_policy);
+            if state.import_settings {
+                *settings = profile.settings;
+                apply_zoom_settings(settings, ctx);
+                ctx.set_theme(settings.ui_theme);
+            }
+            // The screen we came from may be showing data that was just replaced.
+            state.last = Box::new(SelectState::new(&res.levels));
+            state.profile_status = Some(format!("Imported data for {count} levels"));
+        }
+        Err(e) => state.profile_status = Some(format!("Import failed: {e}")),
+    }
+}
+
 pub fn settings_menu_keys(settings: &mut Settings, ctx: &Context) -> SettingsMenu {
     let mut menu = SettingsMenu::Keys;
     central_panel(ctx, egui::Align::Min, |ui| {
@@ -208,6 +316,48 @@ pub fn settings_menu_choose_key(
         .inner
 }
 
+static FINISH_HELP: &str = "A list of car numbers, separated by spaces or commas.\n\
+    0..10 is the cars from 0 to 9, and 0..10 step 2 is every other one.\n\
+    rev(...) reverses a list, and evens(n) and odds(n) are the even and odd cars below n.\n\
+    interleave(a, b) alternates between two lists.\n\
+    repeat(list, n, stride) repeats a list n times, adding stride each time.";
+
+fn allowed_tiles(ui: &mut Ui, banned: &mut EnumMap<TileType, bool>) -> bool {
+    let mut changed = false;
+    ui.vertical(|ui| {
+        for t in TileType::iter().filter(|&t| t != TileType::Finish) {
+            let mut allowed = !banned[t];
+            if ui.checkbox(&mut allowed, t.name()).changed() {
+                banned[t] = !allowed;
+                changed = true;
+            }
+        }
+    });
+    changed
+}
+
+fn export_level_toml(state: &mut CustomSpecState, ctx: &Context, to_file: bool) {
+    let Ok(lvl) = state.to_level() else {
+        return;
+    };
+    let text = levels_to_toml(&[lvl]);
+    if to_file {
+        let result = state.exporter.set_save_action(
+            Box::new(move |w| {
+                w.write_all(text.as_bytes())?;
+                Ok(())
+            }),
+            "level.toml",
+        );
+        if let Err(e) = result {
+            state.export_status = Some(format!("Export failed: {e}"));
+        }
+    } else {
+        ctx.copy_text(text);
+        state.export_status = Some("Copied to the clipboard".to_string());
+    }
+}
+
 pub fn custom_spec_menu(
     gfx: &mut Graphics,
     plugins: &mut Plugins,
@@ -215,11 +365,15 @@ pub fn custom_spec_menu(
 ) -> DialogResponse<LevelData> {
     let mut status = DialogResponse::Waiting;
     let mut output = plugins.egui(|ctx| {
-        egui::Window::new("Level settings").show(ctx, |ui| {
+        egui::Window::new("Level designer").show(ctx, |ui| {
+            let mut changed = false;
             Grid::new("custom level grid").show(ui, |ui| {
+                ui.label("Name");
+                changed |= ui.text_edit_singleline(&mut state.name).changed();
+                ui.end_row();
                 ui.label("# of cars");
-                let mut changed = ui
-                    .add(egui::DragValue::new(&mut state.cars).range(0..=100))
+                changed |= ui
+                    .add(egui::DragValue::new(&mut state.cars).range(0..=MAX_CARS))
                     .changed();
                 ui.end_row();
                 ui.label("Finish order");
@@ -228,30 +382,70 @@ pub fn custom_spec_menu(
                         egui::TextEdit::multiline(&mut state.finish)
                             .min_size(egui::Vec2::new(3.0 * TILE_SIZE, TILE_SIZE)),
                     )
+                    .on_hover_text(FINISH_HELP)
                     .changed();
-                if changed {
-                    state.check_finish();
-                }
+                ui.end_row();
+                ui.label("Round limit");
+                ui.horizontal(|ui| {
+                    changed |= ui.checkbox(&mut state.limit_rounds, "").changed();
+                    changed |= ui
+                        .add_enabled(
+                            state.limit_rounds,
+                            egui::DragValue::new(&mut state.max_rounds).range(1..=usize::MAX),
+                        )
+                        .changed();
+                });
+                ui.end_row();
+                ui.label("Allowed track");
+                changed |= allowed_tiles(ui, &mut state.banned);
+                ui.end_row();
+                ui.label("Tutorial text");
+                changed |= ui
+                    .add(
+                        egui::TextEdit::multiline(&mut state.tutorial_text)
+                            .min_size(egui::Vec2::new(3.0 * TILE_SIZE, TILE_SIZE)),
+                    )
+                    .changed();
+                ui.end_row();
             });
+            if changed {
+                state.check();
+            }
+            if let Some(e) = &state.error {
+                ui.colored_label(Color32::RED, e);
+            }
+            if let Some(s) = &state.export_status {
+                ui.label(s);
+            }
             ui.horizontal(|ui| {
+                let valid = state.error.is_none();
                 if ui
-                    .add_enabled(state.finish_is_valid, egui::Button::new("Ok"))
+                    .add_enabled(valid, egui::Button::new("Save and edit"))
                     .clicked()
+                    && let Ok(lvl) = state.to_level()
                 {
-                    let lvl = LevelData {
-                        name: "Custom Level".to_string(),
-                        cars: state.cars,
-                        finish: state.get_finish().unwrap(),
-                        tutorial: None,
-                        banned: Default::default(),
-                    };
                     status = DialogResponse::Accepted(lvl);
                 }
+                if ui
+                    .add_enabled(valid, egui::Button::new("Copy TOML"))
+                    .clicked()
+                {
+                    export_level_toml(state, ctx, false);
+                }
+                if ui
+                    .add_enabled(valid, egui::Button::new("Export TOML"))
+                    .clicked()
+                {
+                    export_level_toml(state, ctx, true);
+                }
                 if ui.button("Cancel").clicked() {
                     status = DialogResponse::Rejected;
                 }
             })
         });
+        if let Err(e) = state.exporter.update(ctx) {
+            state.export_status = Some(format!("Export failed: {e}"));
+        }
     });
     output.clear_color(Color::BLACK);
     gfx.render(&output);
@@ -1,9 +1,11 @@
+pub mod course_image;
 pub mod credits;
 pub mod edit;
 pub mod export;
 pub mod graphics;
 pub mod gui;
 pub mod home;
+pub mod import;
 pub mod input;
 pub mod loader;
 pub mod menu;
@@ -11,3 +13,4 @@ pub mod race;
 pub mod replay;
 pub mod screen;
 pub mod settings;
+pub mod viewer;
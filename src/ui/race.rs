@@ -1,25 +1,32 @@
 use crate::{
+    course::{TileCoord, bounding_rect},
     direction::Direction,
     input::Action,
     level::LevelData,
-    playback::Playback,
+    metrics::summary,
+    playback::{Breakpoint, MAX_SPEED, MIN_SPEED, Playback},
+    records::{Medal, best_courses, par_comparison},
+    selection::selection_rect,
     states::{RaceEndStatus, RaceState},
     tracker::compute_not_finishing,
+    traffic::{CarEnd, Divergence, Heatmap, route, trail},
 };
 use notan::{
     app::{App, Graphics, Plugins},
     draw::CreateDraw,
-    egui::{self, Button, Context, EguiPluginSugar, Rect, Slider, Ui},
+    egui::{self, Button, Color32, Context, EguiPluginSugar, Rect, RichText, Slider, Ui},
+    math::Vec2,
 };
 use strum::IntoEnumIterator;
 
 use super::{
     edit::key_window,
     graphics::{TILE_SIZE, TileGraphics, get_draw_offset},
-    input::check_key_press,
+    input::{check_key_press, mouse_coords, mouse_pos},
     loader::Resources,
-    replay::make_animation_webp,
+    replay::{GifPalette, ReplayFormat, ReplayFrames, ReplayOptions, make_animation},
     settings::Settings,
+    viewer::export_replay_file,
 };
 
 #[derive(Copy, Clone)]
@@ -38,6 +45,9 @@ impl PlaybackPanelState {
     fn play_pressed(&self) -> bool {
         matches!(self, Self::Viewing(Playback::Playing, _, _))
     }
+    fn play_backward_pressed(&self) -> bool {
+        matches!(self, Self::Viewing(Playback::Backward, _, _))
+    }
     fn pause_pressed(&self) -> bool {
         matches!(self, Self::Viewing(Playback::Paused, _, _))
     }
@@ -84,8 +94,11 @@ impl PlaybackPanelData<'_, '_> {
     }
 }
 
+/// Shows the playback buttons.  `speed` is the playback rate multiplier,
+/// which has a slider while a race is shown.
 pub fn draw_playback_panel(
     state: PlaybackPanelState,
+    speed: Option<&mut f32>,
     settings: &Settings,
     ctx: &Context,
 ) -> Option<Action> {
@@ -106,9 +119,25 @@ pub fn draw_playback_panel(
                 pd.add_button(Action::Settings, "\u{2699}", true);
                 pd.add_button(Action::Keys, "\u{1f5ae}", true);
                 pd.add_button(Action::Copy, "\u{1f4cb}", !state.viewing());
+                pd.add_button(Action::ImportCourse, "\u{1f4c2}", !state.viewing());
+                pd.add_button(Action::ExportCourse, "\u{1f4be}", !state.viewing());
+                pd.add_button(Action::ExportImage, "\u{1f5bc}", !state.viewing());
+                pd.add_button(Action::CopyShareCode, "\u{1f517}", !state.viewing());
+                pd.add_button(Action::PasteShareCode, "\u{1f4e5}", !state.viewing());
+                pd.add_button(Action::Slots, "\u{1f5c2}", !state.viewing());
+                pd.add_button(Action::History, "\u{1f552}", !state.viewing());
+                pd.add_button(Action::GhostRace, "\u{1f47b}", !state.viewing());
                 pd.add_button(Action::Edit, "\u{270f}", state.viewing());
+                pd.add_button(Action::SaveReplay, "\u{1f4fc}", state.viewing());
                 pd.add_button(Action::Start, "\u{23ee}", state.back_enabled());
+                pd.add_button(Action::PrevEvent, "\u{2691}\u{23f4}", state.back_enabled());
                 pd.add_button(Action::StepBack, "|\u{23f4}", state.back_enabled());
+                pd.add_button_selected(
+                    Action::PlayBackward,
+                    "\u{23f4}",
+                    state.back_enabled(),
+                    state.play_backward_pressed(),
+                );
                 pd.add_button_selected(
                     Action::Pause,
                     "\u{23f8}",
@@ -116,6 +145,11 @@ pub fn draw_playback_panel(
                     state.pause_pressed(),
                 );
                 pd.add_button(Action::StepForward, "\u{23f5}|", play_enabled);
+                pd.add_button(
+                    Action::NextEvent,
+                    "\u{23f5}\u{2691}",
+                    play_enabled && state.viewing(),
+                );
                 pd.add_button_selected(
                     Action::Play,
                     "\u{23f5}",
@@ -130,6 +164,15 @@ pub fn draw_playback_panel(
                 if round_new != round_old {
                     pd.command = Some(Action::Seek(round_new));
                 }
+                if let Some(speed) = speed {
+                    pd.ui.add(
+                        Slider::new(speed, MIN_SPEED..=MAX_SPEED)
+                            .logarithmic(true)
+                            .max_decimals(1)
+                            .suffix("\u{d7}")
+                            .text("Speed"),
+                    );
+                }
                 pd.command
             })
             .inner
@@ -137,28 +180,66 @@ pub fn draw_playback_panel(
         .inner
 }
 
+/// How many rounds before the first wrong finish the goal panel seeks to.
+const DIVERGENCE_LEAD: usize = 5;
+
+fn finish_label(ui: &mut Ui, car: usize, wrong: bool) {
+    if wrong {
+        ui.colored_label(Color32::RED, RichText::new(car.to_string()).strong());
+    } else {
+        ui.label(car.to_string());
+    }
+}
+
+/// Shows the goal next to the results of the race, if there are any.
+/// Returns a seek action if the button to go to the first wrong finish was
+/// clicked.
 pub fn draw_goal_panel(
     level: &LevelData,
     finished: Option<&Vec<usize>>,
     crashed: Option<&[bool]>,
+    divergence: Option<&Divergence>,
     ctx: &Context,
-) {
+) -> Option<Action> {
+    let mut command = None;
     egui::SidePanel::left("Goal").show(ctx, |ui| {
         egui::ScrollArea::vertical().show(ui, |ui| {
             ui.heading(&level.name);
+            if let Some(max_rounds) = level.max_rounds {
+                ui.label(format!("Round limit: {max_rounds}"));
+            }
             ui.heading("Finish order");
+            let wrong_place = divergence.map(|d| d.place);
             ui.columns(2, |col| {
                 col[0].label("Goal");
-                for n in &level.finish {
-                    col[0].label(n.to_string());
+                for (place, &n) in level.finish.iter().enumerate() {
+                    finish_label(&mut col[0], n, wrong_place == Some(place));
                 }
                 col[1].label("Actual");
                 if let Some(fin) = finished {
-                    for n in fin {
-                        col[1].label(n.to_string());
+                    for (place, &n) in fin.iter().enumerate() {
+                        finish_label(&mut col[1], n, wrong_place == Some(place));
                     }
                 }
             });
+            if let Some(d) = divergence {
+                let text = match (d.expected, d.actual) {
+                    (_, Some(actual)) => format!(
+                        "Car {actual} finished in place {} in round {}",
+                        d.place + 1,
+                        d.round
+                    ),
+                    (Some(expected), None) => {
+                        format!("Car {expected} did not finish in place {}", d.place + 1)
+                    }
+                    (None, None) => String::new(),
+                };
+                ui.colored_label(Color32::RED, text);
+                let target = d.round.saturating_sub(DIVERGENCE_LEAD);
+                if ui.button(format!("Go to round {target}")).clicked() {
+                    command = Some(Action::Seek(target));
+                }
+            }
             ui.separator();
             ui.heading("Not finishing");
             ui.columns(2, |col| {
@@ -180,6 +261,7 @@ pub fn draw_goal_panel(
             })
         });
     });
+    command
 }
 
 pub(super) fn gfx_size_for(tiles: isize, zoom: f32) -> u32 {
@@ -190,6 +272,106 @@ pub(super) fn anh(s: String) -> anyhow::Error {
     anyhow::anyhow!("{s}")
 }
 
+/// The top left and bottom right tiles of the course.
+fn course_bounds(state: &RaceState) -> (TileCoord, TileCoord) {
+    let (xrange, yrange) = bounding_rect(state.sim.get_course().keys());
+    (
+        TileCoord(*xrange.start(), *yrange.start()),
+        TileCoord(*xrange.end(), *yrange.end()),
+    )
+}
+
+/// Shows the replay format and its options.  `last_round` and `bounds` are
+/// the limits of the race and `size` is the current output size.  Returns
+/// true if the save button was clicked.
+fn replay_options(
+    ui: &mut Ui,
+    options: &mut ReplayOptions,
+    last_round: usize,
+    bounds: (TileCoord, TileCoord),
+    size: (u32, u32),
+) -> bool {
+    ui.separator();
+    egui::ComboBox::from_label("Format")
+        .selected_text(options.format.name())
+        .show_ui(ui, |ui| {
+            for f in ReplayFormat::iter() {
+                ui.selectable_value(&mut options.format, f, f.name());
+            }
+        });
+    ui.add(Slider::new(&mut options.frames_per_round, 1..=30).text("Frames per round"));
+    ui.add(
+        Slider::new(&mut options.speed, 0.25..=4.0)
+            .logarithmic(true)
+            .text("Speed"),
+    );
+    ui.horizontal(|ui| {
+        let mut forever = options.loop_count == 0;
+        ui.checkbox(&mut forever, "Loop forever");
+        if forever {
+            options.loop_count = 0;
+        } else {
+            options.loop_count = options.loop_count.max(1);
+            ui.add(
+                egui::DragValue::new(&mut options.loop_count)
+                    .range(1..=100)
+                    .suffix(" plays"),
+            );
+        }
+    });
+    if options.format == ReplayFormat::Gif {
+        egui::ComboBox::from_label("Palette")
+            .selected_text(options.gif_palette.name())
+            .show_ui(ui, |ui| {
+                for p in GifPalette::iter() {
+                    ui.selectable_value(&mut options.gif_palette, p, p.name());
+                }
+            });
+    }
+    ui.horizontal(|ui| {
+        ui.label("Rounds");
+        let mut last = options.last_round.unwrap_or(last_round).min(last_round);
+        ui.add(egui::DragValue::new(&mut options.first_round).range(0..=last));
+        ui.label("to");
+        ui.add(egui::DragValue::new(&mut last).range(options.first_round..=last_round));
+        options.last_round = (last < last_round).then_some(last);
+    });
+    ui.horizontal(|ui| {
+        let mut crop = options.crop.is_some();
+        ui.checkbox(&mut crop, "Crop");
+        options.crop = crop.then(|| options.crop.unwrap_or(bounds));
+        if crop {
+            ui.label("Drag a rectangle on the course to choose the area");
+        }
+    });
+    ui.horizontal(|ui| {
+        let mut screen_size = options.tile_size.is_none();
+        ui.checkbox(&mut screen_size, "Screen tile size");
+        if screen_size {
+            options.tile_size = None;
+        } else {
+            let tile_size = options.tile_size.get_or_insert(TILE_SIZE);
+            ui.add(Slider::new(tile_size, 8.0..=256.0).suffix(" px"));
+        }
+    });
+    ui.horizontal(|ui| {
+        ui.checkbox(&mut options.car_numbers, "Car numbers");
+        ui.checkbox(&mut options.round_counter, "Round counter");
+        ui.checkbox(&mut options.goal_overlay, "Goal");
+    });
+    ui.horizontal(|ui| {
+        let mut custom = options.bg_color.is_some();
+        ui.checkbox(&mut custom, "Custom background");
+        if !custom {
+            options.bg_color = None;
+        } else {
+            ui.color_edit_button_rgb(options.bg_color.get_or_insert([1.0; 3]));
+        }
+    });
+    ui.label(format!("Size: {} \u{d7} {} pixels", size.0, size.1));
+    ui.button("Save").clicked()
+}
+
 pub fn show_success(
     app: &mut App,
     gfx: &mut Graphics,
@@ -201,9 +383,43 @@ pub fn show_success(
     egui::Window::new("Success!")
         .show(ctx, |ui| {
             let data = state.solve_data();
-            let report = format!("Rounds: {}\nTiles used: {}", data.turns, data.tiles);
+            let metric = settings.metric;
             let mut command = None;
-            ui.label(report);
+            ui.label(summary(&data));
+            if let Some(par) = state.level_data.par {
+                ui.label(format!(
+                    "Par: {} rounds, {} {} ({})",
+                    par.turns,
+                    metric.value_text(&par),
+                    metric.name().to_lowercase(),
+                    par_comparison(&data, &par, metric)
+                ));
+                if let Some(medal) = Medal::for_solve(&data, &par, metric) {
+                    ui.label(format!("{} {} medal", medal.icon(), medal.name()));
+                }
+            }
+            if !state.pareto.is_empty() {
+                ui.separator();
+                ui.label("Best courses");
+                egui::Grid::new("Pareto grid").show(ui, |ui| {
+                    ui.heading(metric.name());
+                    ui.heading("Rounds");
+                    ui.end_row();
+                    for entry in best_courses(&state.pareto, metric) {
+                        ui.label(metric.value_text(&entry.solve))
+                            .on_hover_text(summary(&entry.solve));
+                        ui.label(entry.solve.turns.to_string());
+                        if entry.solve == data {
+                            ui.label("This run");
+                        } else if ui.button("\u{270f} Edit").clicked() {
+                            state.edit.set_course(entry.course.clone());
+                            command = Some(Action::Edit);
+                        }
+                        ui.end_row();
+                    }
+                });
+                ui.separator();
+            }
             ui.horizontal(|ui| {
                 if ui.button("\u{1f3e0} Select level").clicked() {
                     command = Some(Action::Home);
@@ -215,33 +431,355 @@ pub fn show_success(
                     state.status = RaceEndStatus::Finished;
                 }
                 if ui.button("Save replay").clicked() {
-                    let zoom = (app.window().dpi() as f32) * settings.zoom.tile_size;
-                    if let Ok(bytes) =
-                        make_animation_webp(gfx, res, state, zoom, &settings.bg_color)
-                    {
-                        let _ = state.exporter.set_save_action(
-                            Box::new(move |w| {
-                                w.write_all(&bytes)?;
-                                Ok(())
-                            }),
-                            "race.webp",
-                        );
-                    }
+                    state.show_replay_options = !state.show_replay_options;
                 }
             });
+            let zoom = (app.window().dpi() as f32) * settings.zoom.tile_size;
+            if state.show_replay_options {
+                let frames =
+                    ReplayFrames::new(res, state, &state.replay_options, zoom, &settings.bg_color);
+                let size = (frames.width, frames.height);
+                let last_round = state.tracker.rounds_available() - 1;
+                let bounds = course_bounds(state);
+                if !replay_options(ui, &mut state.replay_options, last_round, bounds, size) {
+                    return command;
+                }
+                let options = state.replay_options;
+                let frames = ReplayFrames::new(res, state, &options, zoom, &settings.bg_color);
+                let result = make_animation(gfx, &frames).and_then(|bytes| {
+                    state.exporter.set_save_action(
+                        Box::new(move |w| {
+                            w.write_all(&bytes)?;
+                            Ok(())
+                        }),
+                        options.format.file_name(),
+                    )
+                });
+                if let Err(e) = result {
+                    state.file_error = Some(e.to_string());
+                }
+                state.show_replay_options = false;
+            }
             command
         })?
         .inner?
 }
 
-static PLAYBACK_ACTIONS: &[Action] = &[
+/// Whether the replay crop is being chosen on the course.
+fn cropping(state: &RaceState) -> bool {
+    state.status == RaceEndStatus::ShowingPopup
+        && state.show_replay_options
+        && state.replay_options.crop.is_some()
+}
+
+/// Sets the replay crop to the tiles in the rectangle being dragged.
+fn process_crop_drag(
+    app: &App,
+    settings: &Settings,
+    state: &mut RaceState,
+    offset: &Vec2,
+    in_gui: bool,
+) {
+    if !cropping(state) {
+        state.crop_anchor = None;
+        return;
+    }
+    let (tl, br) = course_bounds(state);
+    let pos = mouse_coords(app, settings, offset);
+    let pos = TileCoord(pos.0.clamp(tl.0, br.0), pos.1.clamp(tl.1, br.1));
+    if app.mouse.left_was_pressed() && !in_gui {
+        state.crop_anchor = Some(pos);
+    }
+    if let Some(anchor) = state.crop_anchor {
+        let (xrange, yrange) = selection_rect(anchor, pos);
+        state.replay_options.crop = Some((
+            TileCoord(*xrange.start(), *yrange.start()),
+            TileCoord(*xrange.end(), *yrange.end()),
+        ));
+        if !app.mouse.left_is_down() {
+            state.crop_anchor = None;
+        }
+    }
+}
+
+pub(super) fn file_error_window(ctx: &Context, error: &mut Option<String>) {
+    if let Some(err) = error {
+        let mut open = true;
+        egui::Window::new("Error").open(&mut open).show(ctx, |ui| {
+            ui.label(err.as_str());
+        });
+        if !open {
+            *error = None;
+        }
+    }
+}
+
+const TRAIL_ROUNDS: usize = 8;
+
+fn overlay_window(ctx: &Context, state: &mut RaceState) {
+    egui::Window::new("Overlays")
+        .anchor(egui::Align2::RIGHT_TOP, (-8.0, 8.0))
+        .default_open(false)
+        .resizable(false)
+        .show(ctx, |ui| {
+            let mut heatmap = state.heatmap;
+            egui::ComboBox::from_label("Heatmap")
+                .selected_text(heatmap.name())
+                .show_ui(ui, |ui| {
+                    for h in Heatmap::iter() {
+                        ui.selectable_value(&mut heatmap, h, h.name());
+                    }
+                });
+            if heatmap != state.heatmap {
+                state.set_heatmap(heatmap);
+            }
+            ui.checkbox(&mut state.show_trails, "Trails");
+            if ui
+                .checkbox(&mut state.show_timeline, "Car timeline")
+                .changed()
+            {
+                state.compute_timelines();
+            }
+            ui.checkbox(&mut state.show_breakpoints, "Breakpoints");
+        });
+}
+
+static BREAKPOINT_KINDS: [&str; 5] = [
+    "Car spawns",
+    "Car finishes",
+    "Car crashes",
+    "Car enters tile",
+    "Finish order diverges",
+];
+
+/// Lets the user choose a kind of breakpoint and its car or tile.
+fn breakpoint_editor(ui: &mut Ui, draft: &mut Breakpoint, num_cars: usize) {
+    let (mut kind, mut car, mut tile) = match *draft {
+        Breakpoint::Spawn(id) => (0, id, TileCoord(0, 0)),
+        Breakpoint::Finish(id) => (1, id, TileCoord(0, 0)),
+        Breakpoint::Crash(id) => (2, id, TileCoord(0, 0)),
+        Breakpoint::EnterTile(pos) => (3, 0, pos),
+        Breakpoint::Divergence => (4, 0, TileCoord(0, 0)),
+    };
+    egui::ComboBox::from_id_salt("Breakpoint kind")
+        .selected_text(BREAKPOINT_KINDS[kind])
+        .show_ui(ui, |ui| {
+            for (n, name) in BREAKPOINT_KINDS.iter().enumerate() {
+                ui.selectable_value(&mut kind, n, *name);
+            }
+        });
+    match kind {
+        0..=2 => {
+            ui.add(
+                egui::DragValue::new(&mut car)
+                    .range(0..=num_cars.saturating_sub(1))
+                    .prefix("Car "),
+            );
+        }
+        3 => {
+            ui.add(egui::DragValue::new(&mut tile.0).prefix("x: "));
+            ui.add(egui::DragValue::new(&mut tile.1).prefix("y: "));
+        }
+        _ => (),
+    }
+    *draft = match kind {
+        0 => Breakpoint::Spawn(car),
+        1 => Breakpoint::Finish(car),
+        2 => Breakpoint::Crash(car),
+        3 => Breakpoint::EnterTile(tile),
+        _ => Breakpoint::Divergence,
+    };
+}
+
+/// Playback pauses when it reaches any of these conditions, and the
+/// next and previous event buttons jump between them.
+pub(super) fn breakpoint_window(
+    ctx: &Context,
+    breakpoints: &mut Vec<Breakpoint>,
+    draft: &mut Breakpoint,
+    open: &mut bool,
+    num_cars: usize,
+) {
+    egui::Window::new("Breakpoints")
+        .open(open)
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                breakpoint_editor(ui, draft, num_cars);
+                if ui.button("Add").clicked() && !breakpoints.contains(draft) {
+                    breakpoints.push(*draft);
+                }
+            });
+            let mut remove = None;
+            for (n, b) in breakpoints.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    if ui.button("\u{1f5d1}").on_hover_text("Remove").clicked() {
+                        remove = Some(n);
+                    }
+                    ui.label(b.description());
+                });
+            }
+            if let Some(n) = remove {
+                breakpoints.remove(n);
+            }
+            if breakpoints.is_empty() {
+                ui.label("Without breakpoints, every spawn, finish and crash is an event.");
+            }
+        });
+}
+
+fn seek_button(ui: &mut Ui, round: Option<usize>) -> Option<usize> {
+    match round {
+        Some(r) => ui.button(r.to_string()).clicked().then_some(r),
+        None => {
+            ui.label("\u{2014}");
+            None
+        }
+    }
+}
+
+/// Lists when each car spawned, stopped and left the course.  Clicking a
+/// round seeks to it and highlights the car.
+fn timeline_panel(ctx: &Context, state: &mut RaceState) -> Option<Action> {
+    let timelines = state.timelines.as_ref()?;
+    let mut seek = None;
+    egui::SidePanel::right("Car timeline").show(ctx, |ui| {
+        ui.heading("Cars");
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            egui::Grid::new("Timeline grid")
+                .striped(true)
+                .show(ui, |ui| {
+                    for heading in ["Car", "Spawn", "Left", "Stops", "Place"] {
+                        ui.strong(heading);
+                    }
+                    ui.end_row();
+                    for t in timelines {
+                        let selected = state.highlighted_car == Some(t.id);
+                        if ui.selectable_label(selected, t.id.to_string()).clicked() {
+                            seek = Some((t.id, None));
+                        }
+                        if let Some(r) = seek_button(ui, t.spawn) {
+                            seek = Some((t.id, Some(r)));
+                        }
+                        let (end, text) = match t.end {
+                            Some(CarEnd::Finished(r)) => (Some(r), "\u{1f3c1}"),
+                            Some(CarEnd::Crashed(r)) => (Some(r), "\u{1f4a5}"),
+                            None => (None, ""),
+                        };
+                        ui.horizontal(|ui| {
+                            // Seek to the last round with the car still on the course.
+                            if let Some(r) = seek_button(ui, end) {
+                                seek = Some((t.id, Some(r - 1)));
+                            }
+                            ui.label(text);
+                        });
+                        ui.label(t.stops.to_string());
+                        ui.label(t.place.map_or(String::new(), |p| p.to_string()));
+                        ui.end_row();
+                    }
+                });
+        });
+    });
+    let (id, round) = seek?;
+    state.highlighted_car = if round.is_none() && state.highlighted_car == Some(id) {
+        None
+    } else {
+        Some(id)
+    };
+    round.map(Action::Seek)
+}
+
+/// How close to the center of a car a click has to be to select it, in
+/// tiles.
+const CAR_CLICK_RADIUS: f32 = 0.3;
+
+/// Where each car is drawn, in car coordinates.  `t` is the time into the
+/// current animation, or `None` if the cars are drawn without animation.
+fn car_positions(state: &RaceState, t: Option<f32>) -> Vec<(usize, Vec2)> {
+    match t {
+        Some(t) => state
+            .player
+            .animations
+            .iter()
+            .map(|anim| (anim.id, anim.position_at_time(t).pos))
+            .collect(),
+        None => state
+            .get_cars()
+            .iter()
+            .map(|car| (car.id, Vec2::new(car.pos.0 as f32, car.pos.1 as f32)))
+            .collect(),
+    }
+}
+
+/// What the car with the given id is doing in the current round.
+fn car_status(state: &RaceState, id: usize) -> String {
+    let cars = state.tracker.get_cars();
+    if let Some(car) = cars[state.player.round].iter().find(|c| c.id == id) {
+        let stopped = state.player.round > 0 && cars[state.player.round - 1].contains(car);
+        return if stopped { "Stopped" } else { "Driving" }.to_string();
+    }
+    let Some(timeline) = state.timelines.as_ref().and_then(|t| t.get(id)) else {
+        return String::new();
+    };
+    match timeline.end {
+        Some(CarEnd::Finished(r)) if r <= state.player.round => match timeline.place {
+            Some(place) => format!("Finished in round {r}, place {place}"),
+            None => format!("Finished in round {r}"),
+        },
+        Some(CarEnd::Crashed(r)) if r <= state.player.round => format!("Crashed in round {r}"),
+        _ => "Not started".to_string(),
+    }
+}
+
+/// Shows the car that was clicked or selected in the timeline.
+fn car_info_window(ctx: &Context, state: &mut RaceState) {
+    let Some(id) = state.highlighted_car else {
+        return;
+    };
+    let tile = state
+        .get_cars()
+        .iter()
+        .find(|c| c.id == id)
+        .map(|c| TileCoord::from(c.tile_pos()));
+    let status = car_status(state, id);
+    let mut open = true;
+    egui::Window::new("Selected car")
+        .anchor(egui::Align2::RIGHT_BOTTOM, (-8.0, -8.0))
+        .open(&mut open)
+        .resizable(false)
+        .show(ctx, |ui| {
+            egui::Grid::new("Car info").show(ui, |ui| {
+                ui.label("Car");
+                ui.label(id.to_string());
+                ui.end_row();
+                ui.label("Tile");
+                ui.label(tile.map_or("-".to_string(), |pos| format!("({}, {})", pos.0, pos.1)));
+                ui.end_row();
+                ui.label("Status");
+                ui.label(status);
+                ui.end_row();
+            });
+            ui.checkbox(&mut state.follow_car, "Follow with camera");
+        });
+    if !open {
+        state.highlighted_car = None;
+        state.follow_car = false;
+    }
+}
+
+pub static PLAYBACK_ACTIONS: &[Action] = &[
     Action::Start,
     Action::StepBack,
+    Action::PlayBackward,
     Action::Pause,
     Action::StepForward,
     Action::Play,
     Action::FastForward,
     Action::End,
+    Action::PrevEvent,
+    Action::NextEvent,
+    Action::Slower,
+    Action::Faster,
 ];
 
 pub fn draw_race(
@@ -265,9 +803,6 @@ pub fn draw_race(
         }
     }
     if state.status == RaceEndStatus::Simulating {
-        while !state.is_finished() {
-            state.sim_round();
-        }
         state.check_finished();
     }
     if state.status == RaceEndStatus::PopupQueued && state.is_at_end() {
@@ -275,40 +810,91 @@ pub fn draw_race(
     }
     let mut command: Option<Action> = None;
     let mut draw_rect = Rect::NOTHING;
+    let mut mouse_in_gui = false;
     let output = plugins.egui(|ctx| {
-        draw_goal_panel(
+        let seek = draw_goal_panel(
             &state.level_data,
             Some(state.tracker.get_finishes()),
             Some(state.tracker.get_crashes()),
+            state.player.divergence.as_ref(),
             ctx,
         );
         let pps = PlaybackPanelState::Viewing(
-            state.playback,
-            state.round,
+            state.player.playback,
+            state.player.round,
             state.tracker.rounds_available(),
         );
-        command = draw_playback_panel(pps, settings, ctx);
+        command = draw_playback_panel(pps, Some(&mut state.player.speed), settings, ctx).or(seek);
         if state.status == RaceEndStatus::ShowingPopup {
             command = command.or(show_success(app, gfx, res, settings, state, ctx));
         }
+        overlay_window(ctx, state);
+        if state.show_timeline {
+            command = command.or(timeline_panel(ctx, state));
+        }
+        breakpoint_window(
+            ctx,
+            &mut state.player.breakpoints,
+            &mut state.breakpoint_draft,
+            &mut state.show_breakpoints,
+            state.level_data.cars,
+        );
+        car_info_window(ctx, state);
         draw_rect = ctx.available_rect() * ctx.zoom_factor();
+        mouse_in_gui = ctx.is_pointer_over_area();
         if state.show_keys {
             key_window(ctx, settings, false);
         }
         let _ = state.exporter.update(ctx);
+        file_error_window(ctx, &mut state.file_error);
     });
-    if let Some(cmd) = command {
-        state.process_command(cmd, time);
+    match command {
+        Some(Action::SaveReplay) => {
+            if let Err(e) = export_replay_file(
+                state.exporter.as_mut(),
+                &state.level_data,
+                state.sim.get_course(),
+                state.sim.seed(),
+                &state.tracker,
+            ) {
+                state.file_error = Some(e.to_string());
+            }
+        }
+        Some(cmd) => state.process_command(cmd, time),
+        None => (),
     }
     state.check_advance(time);
+    let player = &state.player;
     let round = if settings.smooth_animation
-        && !state.animations.is_empty()
-        && time < state.round_display_time + state.playback.frame_duration()
+        && !player.animations.is_empty()
+        && time < player.round_display_time + player.round_duration()
     {
-        state.round - 1
+        player.animation_round
     } else {
-        state.round
+        player.round
     };
+    let t = (settings.smooth_animation && !player.animations.is_empty())
+        .then(|| (time - player.round_display_time).div_duration_f32(player.round_duration()));
+    let positions = car_positions(state, t);
+    if state.follow_car
+        && let Some(id) = state.highlighted_car
+        && let Some((_, pos)) = positions.iter().find(|(car, _)| *car == id)
+    {
+        state.view_center = 0.5 * *pos;
+    }
+    let offset = get_draw_offset(&state.view_center, &draw_rect);
+    process_crop_drag(app, settings, state, &offset, mouse_in_gui);
+    if app.mouse.left_was_pressed() && !mouse_in_gui && !cropping(state) {
+        let mouse = mouse_pos(app, settings, &offset);
+        let clicked = positions
+            .iter()
+            .find(|(_, pos)| (0.5 * (*pos + Vec2::ONE)).distance(mouse) < CAR_CLICK_RADIUS);
+        state.highlighted_car = clicked.map(|(id, _)| *id);
+        state.follow_car = clicked.is_some();
+        if clicked.is_some() {
+            state.compute_timelines();
+        }
+    }
     let mut graphics = TileGraphics {
         res,
         zoom: settings.zoom.tile_size,
@@ -316,22 +902,47 @@ pub fn draw_race(
         draw: gfx.create_draw(),
         round,
     };
-    let offset = get_draw_offset(&state.view_center, &draw_rect);
     graphics.set_offset(&offset);
     graphics.draw_course(state.sim.get_course());
-    if settings.smooth_animation && !state.animations.is_empty() {
-        let t = (time - state.round_display_time).div_duration_f32(state.playback.frame_duration());
-        for anim in state.animations.iter() {
+    if let Some(traffic) = &state.traffic
+        && state.heatmap != Heatmap::Off
+    {
+        graphics.draw_heatmap(state.sim.get_course(), |pos| {
+            traffic.get(&pos).map_or(0, |t| state.heatmap.value(t))
+        });
+    }
+    if state.show_trails {
+        for car in &state.tracker.get_cars()[round] {
+            let positions = trail(&state.tracker, car.id, round, TRAIL_ROUNDS);
+            graphics.draw_trail(car.id, &positions);
+        }
+    }
+    if let Some(id) = state.highlighted_car {
+        graphics.draw_route(id, &route(&state.tracker, id, round));
+    }
+    if let Some(t) = t {
+        for anim in state.player.animations.iter() {
             let pos = anim.position_at_time(t);
+            if state.highlighted_car == Some(anim.id) {
+                graphics.draw_car_highlight(pos.pos);
+            }
             graphics.draw_car_smooth(anim.id, &pos);
             graphics.draw_car_number_smooth(anim.id, &pos);
         }
     } else {
         for car in state.get_cars() {
+            if state.highlighted_car == Some(car.id) {
+                graphics.draw_car_highlight(Vec2::new(car.pos.0 as f32, car.pos.1 as f32));
+            }
             graphics.draw_car(car);
             graphics.draw_car_number(car);
         }
     }
+    if cropping(state)
+        && let Some((tl, br)) = state.replay_options.crop
+    {
+        graphics.draw_tile_rect(tl, br);
+    }
     gfx.render(&graphics.draw);
     gfx.render(&output);
     command
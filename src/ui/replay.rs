@@ -1,10 +1,19 @@
+use std::ops::RangeInclusive;
+
+use hashbrown::HashMap;
+
 use notan::{
-    app::Graphics,
-    draw::CreateDraw,
+    app::{Color, Graphics},
+    draw::{CreateDraw, DrawShapes, DrawTextSection, DrawTransform},
     math::{Affine2, Mat2, Vec2},
 };
+use strum::EnumIter;
 
-use crate::{course::bounding_rect, states::RaceState};
+use crate::{
+    course::{TileCoord, bounding_rect},
+    playback::{Playback, animations},
+    states::RaceState,
+};
 
 use super::{
     graphics::{TILE_SIZE, TileGraphics},
@@ -12,115 +21,422 @@ use super::{
     race::{anh, gfx_size_for},
 };
 
-pub(super) fn make_animation_webp(
-    gfx: &mut Graphics,
-    res: &Resources,
-    state: &RaceState,
+#[derive(Clone, Copy, PartialEq, Eq, EnumIter)]
+pub enum ReplayFormat {
+    WebP,
+    Gif,
+    Apng,
+}
+
+impl ReplayFormat {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::WebP => "WebP",
+            Self::Gif => "GIF",
+            Self::Apng => "APNG",
+        }
+    }
+
+    pub const fn file_name(self) -> &'static str {
+        match self {
+            Self::WebP => "race.webp",
+            Self::Gif => "race.gif",
+            Self::Apng => "race.png",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, EnumIter)]
+pub enum GifPalette {
+    /// A palette chosen separately for each frame.  Looks better, but is
+    /// slower to encode.
+    Adaptive,
+    /// The 216 color web-safe palette.
+    WebSafe,
+}
+
+impl GifPalette {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Adaptive => "Adaptive",
+            Self::WebSafe => "Web-safe",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct ReplayOptions {
+    pub format: ReplayFormat,
+    /// The number of frames used to animate the cars between rounds.  With
+    /// a single frame, the cars jump from one position to the next.
+    pub frames_per_round: usize,
+    /// Playback speed relative to the normal in-game speed.
+    pub speed: f32,
+    /// The number of times to play the animation, or 0 to loop forever.
+    pub loop_count: u16,
+    pub gif_palette: GifPalette,
+    pub first_round: usize,
+    /// The last round to export, or `None` for the end of the race.
+    pub last_round: Option<usize>,
+    /// The top left and bottom right tiles to show.  If this is `None`, the
+    /// whole course is shown.
+    pub crop: Option<(TileCoord, TileCoord)>,
+    /// The width of a tile in pixels.  If this is `None`, tiles are the same
+    /// size as on screen.
+    pub tile_size: Option<f32>,
+    pub car_numbers: bool,
+    pub round_counter: bool,
+    pub goal_overlay: bool,
+    /// If this is `None`, the track background from the settings is used.
+    pub bg_color: Option<[f32; 3]>,
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        Self {
+            format: ReplayFormat::WebP,
+            frames_per_round: 8,
+            speed: 1.0,
+            loop_count: 0,
+            gif_palette: GifPalette::Adaptive,
+            first_round: 0,
+            last_round: None,
+            crop: None,
+            tile_size: None,
+            car_numbers: true,
+            round_counter: false,
+            goal_overlay: false,
+            bg_color: None,
+        }
+    }
+}
+
+impl ReplayOptions {
+    pub fn frame_delay_ms(&self) -> u16 {
+        let round_ms = Playback::Playing.frame_duration().as_secs_f32() * 1000.0;
+        (round_ms / (self.speed * self.frames_per_round as f32))
+            .round()
+            .clamp(1.0, u16::MAX as f32) as u16
+    }
+
+    /// The rounds to export, limited to the rounds in the race.
+    pub fn rounds(&self, state: &RaceState) -> RangeInclusive<usize> {
+        let max = state.tracker.rounds_available() - 1;
+        let last = self.last_round.unwrap_or(max).min(max);
+        self.first_round.min(last)..=last
+    }
+}
+
+/// The parts of the race to export and the size of the frames.
+pub(super) struct ReplayFrames<'a> {
+    res: &'a Resources,
+    state: &'a RaceState,
+    options: &'a ReplayOptions,
     zoom: f32,
-    bg_color: &[f32; 3],
+    bg_color: [f32; 3],
+    xrange: RangeInclusive<isize>,
+    yrange: RangeInclusive<isize>,
+    rounds: RangeInclusive<usize>,
+    /// The height of the text below the course.
+    bar_height: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl<'a> ReplayFrames<'a> {
+    /// `zoom` and `bg_color` are used unless the options override them.
+    pub fn new(
+        res: &'a Resources,
+        state: &'a RaceState,
+        options: &'a ReplayOptions,
+        zoom: f32,
+        bg_color: &[f32; 3],
+    ) -> Self {
+        let zoom = options.tile_size.map_or(zoom, |sz| sz / TILE_SIZE);
+        let (xrange, yrange) = match options.crop {
+            Some((tl, br)) => (
+                tl.0.min(br.0)..=tl.0.max(br.0),
+                tl.1.min(br.1)..=tl.1.max(br.1),
+            ),
+            None => bounding_rect(state.sim.get_course().keys()),
+        };
+        let lines = usize::from(options.round_counter) + 2 * usize::from(options.goal_overlay);
+        let bar_height = (lines as f32 * Self::line_height(zoom)).ceil() as u32;
+        // WebP requires even dimensions.
+        let width = gfx_size_for(xrange.end() - xrange.start() + 1, zoom) & !0x1;
+        let height = (gfx_size_for(yrange.end() - yrange.start() + 1, zoom) + bar_height) & !0x1;
+        Self {
+            res,
+            state,
+            options,
+            zoom,
+            bg_color: options.bg_color.unwrap_or(*bg_color),
+            xrange,
+            yrange,
+            rounds: options.rounds(state),
+            bar_height,
+            width,
+            height,
+        }
+    }
+
+    fn line_height(zoom: f32) -> f32 {
+        (0.3 * TILE_SIZE * zoom).max(12.0)
+    }
+
+    /// The number of frames that `render` produces.
+    pub fn count(&self) -> usize {
+        1 + (self.rounds.end() - self.rounds.start()) * self.options.frames_per_round
+    }
+
+    /// The finished cars in order, each with the first round in which it is
+    /// no longer on the course.
+    fn finish_rounds(&self) -> Vec<(usize, usize)> {
+        let mut last_seen = HashMap::new();
+        for (round, cars) in self.state.tracker.get_cars().iter().enumerate() {
+            for car in cars {
+                last_seen.insert(car.id, round);
+            }
+        }
+        self.state
+            .tracker
+            .get_finishes()
+            .iter()
+            .filter_map(|&id| Some((id, last_seen.get(&id)? + 1)))
+            .collect()
+    }
+
+    fn overlay_text(&self, round: usize, finish_rounds: &[(usize, usize)]) -> Vec<String> {
+        let mut lines = Vec::new();
+        if self.options.round_counter {
+            lines.push(format!("Round {round}"));
+        }
+        if self.options.goal_overlay {
+            let list = |cars: &[usize]| {
+                let names: Vec<String> = cars.iter().map(|n| n.to_string()).collect();
+                names.join(" ")
+            };
+            lines.push(format!("Goal: {}", list(&self.state.level_data.finish)));
+            let finished: Vec<usize> = finish_rounds
+                .iter()
+                .filter(|&&(_, r)| r <= round)
+                .map(|&(id, _)| id)
+                .collect();
+            lines.push(format!("Finished: {}", list(&finished)));
+        }
+        lines
+    }
+
+    fn draw_overlay(
+        &self,
+        graphics: &mut TileGraphics,
+        round: usize,
+        finish_rounds: &[(usize, usize)],
+    ) {
+        let lines = self.overlay_text(round, finish_rounds);
+        if lines.is_empty() {
+            return;
+        }
+        let width = self.width as f32;
+        let height = self.height as f32;
+        let bar_top = height - self.bar_height as f32;
+        // Frames are read back upside down, so the overlay is flipped in the
+        // same way as the course.
+        let flip = Affine2::from_mat2_translation(
+            Mat2::from_diagonal(Vec2::new(1.0, -1.0)),
+            Vec2::new(0.0, height),
+        );
+        graphics.draw.transform().push(flip.into());
+        graphics
+            .draw
+            .rect((0.0, bar_top), (width, self.bar_height as f32))
+            .color(Color::WHITE);
+        let line_height = Self::line_height(self.zoom);
+        for (n, line) in lines.iter().enumerate() {
+            graphics
+                .draw
+                .text(&self.res.font, line)
+                .position(0.25 * line_height, bar_top + n as f32 * line_height)
+                .size(0.7 * line_height)
+                .color(Color::BLACK);
+        }
+        graphics.draw.transform().pop();
+    }
+
+    /// Renders the frames and passes the RGBA pixels of each one to
+    /// `add_frame`.  The first frame shows the first round, and each later
+    /// round is animated over `frames_per_round` frames in the same way as
+    /// the in-game smooth animation.
+    fn render<F>(&self, gfx: &mut Graphics, mut add_frame: F) -> Result<(), anyhow::Error>
+    where
+        F: FnMut(&mut [u8]) -> Result<(), anyhow::Error>,
+    {
+        let course = self.state.sim.get_course();
+        let tile_size_zoom = TILE_SIZE * self.zoom;
+        let texture = gfx
+            .create_render_texture(self.width, self.height)
+            .build()
+            .map_err(anh)?;
+        let mut pix = vec![0; 4 * (self.width as usize) * (self.height as usize)];
+        let xoff = (*self.xrange.start() as f32) * tile_size_zoom;
+        let yoff = ((*self.yrange.end() + 1) as f32) * tile_size_zoom + self.bar_height as f32;
+        let aff = Affine2::from_mat2_translation(
+            Mat2::from_diagonal(Vec2::new(1.0, -1.0)),
+            Vec2::new(-xoff, yoff),
+        );
+        let cars = self.state.tracker.get_cars();
+        let finish_rounds = self.finish_rounds();
+        let first = *self.rounds.start();
+        for round in self.rounds.clone() {
+            let (anims, steps) = if round == first {
+                (Vec::new(), 1)
+            } else {
+                (
+                    animations(&cars[round - 1], &cars[round]),
+                    self.options.frames_per_round,
+                )
+            };
+            for step in 1..=steps {
+                let moving = step < steps;
+                let mut graphics = TileGraphics {
+                    res: self.res,
+                    zoom: self.zoom,
+                    bg_color: &self.bg_color,
+                    draw: texture.create_draw(),
+                    round: if moving { round - 1 } else { round },
+                };
+                graphics.draw.transform().push(aff.into());
+                graphics.draw_course(course);
+                if moving {
+                    let t = step as f32 / steps as f32;
+                    for anim in &anims {
+                        let pos = anim.position_at_time(t);
+                        graphics.draw_car_smooth(anim.id, &pos);
+                        if self.options.car_numbers {
+                            graphics.draw_car_number_smooth(anim.id, &pos);
+                        }
+                    }
+                } else {
+                    for car in &cars[round] {
+                        graphics.draw_car(car);
+                        if self.options.car_numbers {
+                            graphics.draw_car_number(car);
+                        }
+                    }
+                }
+                graphics.draw.transform().pop();
+                self.draw_overlay(&mut graphics, round, &finish_rounds);
+                gfx.render_to(&texture, &graphics.draw);
+                gfx.read_pixels(&texture).read_to(&mut pix).map_err(anh)?;
+                add_frame(&mut pix)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(super) fn make_animation(
+    gfx: &mut Graphics,
+    frames: &ReplayFrames,
+) -> Result<Vec<u8>, anyhow::Error> {
+    match frames.options.format {
+        ReplayFormat::WebP => make_animation_webp(gfx, frames),
+        ReplayFormat::Gif => make_animation_gif(gfx, frames),
+        ReplayFormat::Apng => make_animation_apng(gfx, frames),
+    }
+}
+
+fn make_animation_webp(
+    gfx: &mut Graphics,
+    frames: &ReplayFrames,
 ) -> Result<Vec<u8>, anyhow::Error> {
-    let course = state.sim.get_course();
-    let (xrange, yrange) = bounding_rect(course.keys());
-    let tile_size_zoom = TILE_SIZE * zoom;
-    let width = gfx_size_for(xrange.end() - xrange.start() + 1, zoom) & !0x1;
-    let height = gfx_size_for(yrange.end() - yrange.start() + 1, zoom) & !0x1;
-    let texture = gfx
-        .create_render_texture(width, height)
-        .build()
-        .map_err(anh)?;
-    let mut pix = vec![0; 4 * (width as usize) * (height as usize)];
+    let (width, height) = (frames.width, frames.height);
+    let delay = frames.options.frame_delay_ms();
     let params = webp_animator::Params {
         width,
         height,
         background_bgra: [0xFF; 4],
-        loop_count: 0,
+        loop_count: frames.options.loop_count.into(),
         has_alpha: true,
     };
     let mut encoder = webp_animator::WebPAnimator::new(params)?;
-    let xoff = (*xrange.start() as f32) * tile_size_zoom;
-    let yoff = ((*yrange.end() + 1) as f32) * tile_size_zoom;
-    let aff = Affine2::from_mat2_translation(
-        Mat2::from_diagonal(Vec2::new(1.0, -1.0)),
-        Vec2::new(-xoff, yoff),
-    );
     let mut frame_buf = Vec::new();
-    for round in 0..state.tracker.rounds_available() {
-        let mut graphics = TileGraphics {
-            res,
-            zoom,
-            bg_color,
-            draw: texture.create_draw(),
-            round,
-        };
-        graphics.draw.transform().push(aff.into());
-        graphics.draw_course(course);
-        for car in &state.tracker.get_cars()[round] {
-            graphics.draw_car(car);
-            graphics.draw_car_number(car);
-        }
-        gfx.render_to(&texture, &graphics.draw);
-        gfx.read_pixels(&texture).read_to(&mut pix).map_err(anh)?;
+    frames.render(gfx, |pix| {
         frame_buf.clear();
         let frame_enc = image_webp::WebPEncoder::new(&mut frame_buf);
-        frame_enc.encode(&pix, width, height, image_webp::ColorType::Rgba8)?;
-        encoder.add_webp_image(&frame_buf, None, 500)?;
-    }
+        frame_enc.encode(pix, width, height, image_webp::ColorType::Rgba8)?;
+        encoder.add_webp_image(&frame_buf, None, delay.into())?;
+        Ok(())
+    })?;
     let mut out = Vec::new();
     encoder.write(&mut out)?;
     Ok(out)
 }
 
-/*
-pub(super) fn make_animation_png(
+/// Maps an RGBA image to the web-safe palette.
+fn web_safe_indices(pix: &[u8]) -> Vec<u8> {
+    let level = |c: u8| (c as u16 * 5 + 127) / 255;
+    pix.chunks_exact(4)
+        .map(|p| (36 * level(p[0]) + 6 * level(p[1]) + level(p[2])) as u8)
+        .collect()
+}
+
+fn web_safe_palette() -> Vec<u8> {
+    (0..216)
+        .flat_map(|n: u8| [n / 36, (n / 6) % 6, n % 6].map(|c| c * 51))
+        .collect()
+}
+
+fn make_animation_gif(gfx: &mut Graphics, frames: &ReplayFrames) -> Result<Vec<u8>, anyhow::Error> {
+    let options = frames.options;
+    let (w16, h16) = (u16::try_from(frames.width)?, u16::try_from(frames.height)?);
+    let delay = options.frame_delay_ms();
+    let palette = web_safe_palette();
+    let global_palette: &[u8] = match options.gif_palette {
+        GifPalette::Adaptive => &[],
+        GifPalette::WebSafe => &palette,
+    };
+    let mut encoder = gif::Encoder::new(Vec::new(), w16, h16, global_palette)?;
+    // The GIF repeat count doesn't include the first time through, and a
+    // count of 0 means forever, so a single play is written as no repeat.
+    match options.loop_count {
+        0 => encoder.set_repeat(gif::Repeat::Infinite)?,
+        1 => (),
+        n => encoder.set_repeat(gif::Repeat::Finite(n - 1))?,
+    }
+    frames.render(gfx, |pix| {
+        let mut frame = match options.gif_palette {
+            GifPalette::Adaptive => gif::Frame::from_rgba_speed(w16, h16, pix, 10),
+            GifPalette::WebSafe => gif::Frame {
+                width: w16,
+                height: h16,
+                buffer: web_safe_indices(pix).into(),
+                ..Default::default()
+            },
+        };
+        frame.delay = delay.div_ceil(10);
+        encoder.write_frame(&frame)?;
+        Ok(())
+    })?;
+    Ok(encoder.into_inner()?)
+}
+
+fn make_animation_apng(
     gfx: &mut Graphics,
-    res: &Resources,
-    state: &RaceState,
-    zoom: f32,
-    bg_color: &[f32; 3],
+    frames: &ReplayFrames,
 ) -> Result<Vec<u8>, anyhow::Error> {
-    let mut out = Cursor::new(Vec::new());
-    let course = state.sim.get_course();
-    let (xrange, yrange) = bounding_rect(course.keys());
-    let tile_size_zoom = TILE_SIZE * zoom;
-    let width = gfx_size_for(xrange.end() - xrange.start() + 1, zoom);
-    let height = gfx_size_for(yrange.end() - yrange.start() + 1, zoom);
-    let texture = gfx
-        .create_render_texture(width, height)
-        .build()
-        .map_err(anh)?;
-    let mut pix = vec![0; 4 * (width as usize) * (height as usize)];
-    let mut encoder = png::Encoder::new(&mut out, width, height);
+    let options = frames.options;
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, frames.width, frames.height);
     encoder.set_color(png::ColorType::Rgba);
-    encoder
-        .set_animated(state.tracker.rounds_available() as u32, 0)
-        .unwrap();
-    let mut writer = encoder.write_header().unwrap();
-    writer.set_frame_delay(1, 2).unwrap();
-    let xoff = (*xrange.start() as f32) * tile_size_zoom;
-    let yoff = ((*yrange.end() + 1) as f32) * tile_size_zoom;
-    let aff = Affine2::from_mat2_translation(
-        Mat2::from_diagonal(Vec2::new(1.0, -1.0)),
-        Vec2::new(-xoff, yoff),
-    );
-    for round in 0..state.tracker.rounds_available() {
-        let mut graphics = TileGraphics {
-            res,
-            zoom,
-            bg_color,
-            draw: texture.create_draw(),
-            round,
-        };
-        graphics.draw.transform().push(aff.into());
-        graphics.draw_course(course);
-        for car in &state.tracker.get_cars()[round] {
-            graphics.draw_car(car);
-            graphics.draw_car_number(car);
-        }
-        gfx.render_to(&texture, &graphics.draw);
-        gfx.read_pixels(&texture).read_to(&mut pix).map_err(anh)?;
-        writer.write_image_data(&pix).unwrap();
-    }
-    writer.finish().unwrap();
-    Ok(out.into_inner())
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(frames.count() as u32, options.loop_count.into())?;
+    encoder.set_frame_delay(options.frame_delay_ms(), 1000)?;
+    let mut writer = encoder.write_header()?;
+    frames.render(gfx, |pix| {
+        writer.write_image_data(pix)?;
+        Ok(())
+    })?;
+    writer.finish()?;
+    Ok(out)
 }
-*/
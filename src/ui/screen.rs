@@ -3,10 +3,13 @@ use std::rc::Rc;
 use notan::app::{App, Graphics, Plugins};
 
 use crate::{
+    course::Course,
     input::Action,
+    level::LevelData,
+    save::{add_custom_level, add_unknown_level, load_course, load_custom_levels, save_course},
     states::{
-        CustomSpecState, DialogResponse, EditState, RaceState, SelectState, SelectStatus,
-        SettingsState,
+        CustomSpecState, DialogResponse, EditState, RaceState, ReplayState, SelectState,
+        SelectStatus, SettingsState,
     },
 };
 
@@ -18,8 +21,19 @@ use super::{
     menu::{custom_spec_menu, settings_menu},
     race::draw_race,
     settings::Settings,
+    viewer::draw_viewer,
 };
 
+/// Opens a course for a level that may not be installed yet, adding the
+/// level to the custom levels if necessary.
+fn open_course(level: LevelData, course: Course) -> Box<dyn Screen> {
+    add_unknown_level(&level);
+    if load_course(&level).is_none() {
+        save_course(&level, &course);
+    }
+    Box::new(EditState::new_with_course(Rc::new(level), course))
+}
+
 pub trait Screen {
     fn run(
         self: Box<Self>,
@@ -29,6 +43,9 @@ pub trait Screen {
         res: &Resources,
         settings: &mut Settings,
     ) -> Box<dyn Screen>;
+
+    /// Saves anything that is otherwise only saved when leaving the screen.
+    fn save_on_exit(&mut self) {}
 }
 
 impl Screen for EditState {
@@ -40,9 +57,17 @@ impl Screen for EditState {
         res: &Resources,
         settings: &mut Settings,
     ) -> Box<dyn Screen> {
-        match draw_edit(app, gfx, plugins, res, settings, &mut self) { Some(command) => {
+        let command = draw_edit(app, gfx, plugins, res, settings, &mut self);
+        if let Some(shared) = self.open_level.take() {
+            self.course.flush_history();
+            return open_course(shared.level, shared.course);
+        }
+        match command { Some(command) => {
             match command {
-                Action::Home => Box::new(SelectState::new(&res.levels)),
+                Action::Home => {
+                    self.course.flush_history();
+                    Box::new(SelectState::new(&res.levels))
+                }
                 Action::StepForward | Action::Play | Action::FastForward => {
                     let mut new_state =
                         RaceState::new(self.level_data, self.course, self.view_center);
@@ -56,6 +81,10 @@ impl Screen for EditState {
             self
         }}
     }
+
+    fn save_on_exit(&mut self) {
+        self.course.flush_history();
+    }
 }
 
 impl Screen for SettingsState {
@@ -74,20 +103,31 @@ impl Screen for SettingsState {
             self
         }
     }
+
+    fn save_on_exit(&mut self) {
+        self.last.save_on_exit();
+    }
 }
 
 impl Screen for SelectState {
     fn run(
-        self: Box<Self>,
+        mut self: Box<Self>,
         app: &mut App,
         gfx: &mut Graphics,
         plugins: &mut Plugins,
-        res: &Resources,
-        _settings: &mut Settings,
+        _res: &Resources,
+        settings: &mut Settings,
     ) -> Box<dyn Screen> {
-        match draw_home_screen(app, gfx, plugins, res, &self) {
-            SelectStatus::Level(n) => Box::new(EditState::new(res.levels[n].clone())),
-            SelectStatus::Custom => Box::new(CustomSpecState::new()),
+        match draw_home_screen(app, gfx, plugins, settings, &mut self) {
+            SelectStatus::Level(lvl) => Box::new(EditState::new(lvl)),
+            SelectStatus::LevelWithCourse(lvl, course) => {
+                Box::new(EditState::with_restored_course(lvl, course))
+            }
+            SelectStatus::Replay(replay) => Box::new(ReplayState::new(*replay)),
+            SelectStatus::Custom => Box::new(CustomSpecState::new().avoiding(load_custom_levels())),
+            SelectStatus::Design(lvl) => {
+                Box::new(CustomSpecState::from_level(&lvl).avoiding(load_custom_levels()))
+            }
             SelectStatus::Settings => Box::new(SettingsState::new(self)),
             SelectStatus::Credits => Box::new(CreditsState),
             SelectStatus::Idle => self,
@@ -105,7 +145,10 @@ impl Screen for RaceState {
         settings: &mut Settings,
     ) -> Box<dyn Screen> {
         match draw_race(app, gfx, plugins, res, settings, &mut self) {
-            Some(Action::Home) => Box::new(SelectState::new(&res.levels)),
+            Some(Action::Home) => {
+                self.edit.flush_history();
+                Box::new(SelectState::new(&res.levels))
+            }
             Some(Action::Edit) => Box::new(EditState::new_with_course_edit_and_center(
                 self.level_data,
                 self.edit,
@@ -115,6 +158,28 @@ impl Screen for RaceState {
             _ => self,
         }
     }
+
+    fn save_on_exit(&mut self) {
+        self.edit.flush_history();
+    }
+}
+
+impl Screen for ReplayState {
+    fn run(
+        mut self: Box<Self>,
+        app: &mut App,
+        gfx: &mut Graphics,
+        plugins: &mut Plugins,
+        res: &Resources,
+        settings: &mut Settings,
+    ) -> Box<dyn Screen> {
+        match draw_viewer(app, gfx, plugins, res, settings, &mut self) {
+            Some(Action::Home) => Box::new(SelectState::new(&res.levels)),
+            Some(Action::Edit) => open_course(self.level_data.as_ref().clone(), self.course),
+            Some(Action::Settings) => Box::new(SettingsState::new(self)),
+            _ => self,
+        }
+    }
 }
 
 impl Screen for CustomSpecState {
@@ -127,7 +192,10 @@ impl Screen for CustomSpecState {
         _settings: &mut Settings,
     ) -> Box<dyn Screen> {
         match custom_spec_menu(gfx, plugins, &mut self) {
-            DialogResponse::Accepted(data) => Box::new(EditState::new(Rc::new(data))),
+            DialogResponse::Accepted(data) => {
+                add_custom_level(&data);
+                Box::new(EditState::new(Rc::new(data)))
+            }
             DialogResponse::Rejected => Box::new(SelectState::new(&res.levels)),
             DialogResponse::Waiting => self,
         }
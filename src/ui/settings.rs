@@ -1,4 +1,4 @@
-use crate::{direction::Direction, input::Action, tile::TileType};
+use crate::{direction::Direction, input::Action, metrics::Metric, tile::TileType};
 
 use indexmap::IndexMap;
 use notan::{egui, prelude::KeyCode};
@@ -41,11 +41,16 @@ static KEYS_2: &[(Action, KeyCode)] = &[
     (Action::Delete, KeyCode::Backspace),
     (Action::Start, KeyCode::KeyC),
     (Action::StepBack, KeyCode::KeyV),
+    (Action::PlayBackward, KeyCode::KeyX),
     (Action::Pause, KeyCode::KeyB),
     (Action::StepForward, KeyCode::KeyN),
     (Action::Play, KeyCode::KeyM),
     (Action::FastForward, KeyCode::Comma),
     (Action::End, KeyCode::Period),
+    (Action::PrevEvent, KeyCode::BracketLeft),
+    (Action::NextEvent, KeyCode::BracketRight),
+    (Action::Slower, KeyCode::Minus),
+    (Action::Faster, KeyCode::Equal),
 ];
 
 pub fn default_key_settings() -> KeySettings {
@@ -96,6 +101,7 @@ pub struct Settings {
     pub zoom: ZoomSettings,
     pub ui_theme: egui::ThemePreference,
     pub bg_color: [f32; 3],
+    pub metric: Metric,
 }
 
 impl Settings {
@@ -114,6 +120,7 @@ impl Default for Settings {
             zoom: Default::default(),
             ui_theme: egui::ThemePreference::System,
             bg_color: [1.0, 1.0, 1.0],
+            metric: Metric::default(),
         }
     }
 }
@@ -0,0 +1,171 @@
+use notan::{
+    app::{App, Graphics, Plugins},
+    draw::CreateDraw,
+    egui::{self, EguiPluginSugar, Rect},
+};
+use strum::IntoEnumIterator;
+
+use crate::{
+    course::Course,
+    direction::Direction,
+    input::Action,
+    level::LevelData,
+    replay_file::{REPLAY_FILE_NAME, ReplayFile},
+    states::ReplayState,
+    tracker::Tracker,
+};
+
+use super::{
+    edit::key_window,
+    export::FileExport,
+    graphics::{TileGraphics, get_draw_offset},
+    input::check_key_press,
+    loader::Resources,
+    race::{
+        PlaybackPanelState, breakpoint_window, draw_goal_panel, draw_playback_panel,
+        file_error_window,
+    },
+    settings::Settings,
+};
+
+pub static VIEWER_ACTIONS: &[Action] = &[
+    Action::Start,
+    Action::StepBack,
+    Action::PlayBackward,
+    Action::Pause,
+    Action::StepForward,
+    Action::Play,
+    Action::FastForward,
+    Action::End,
+    Action::PrevEvent,
+    Action::NextEvent,
+    Action::Slower,
+    Action::Faster,
+];
+
+/// Offers a finished race for download as a `.racereplay` file.
+pub(super) fn export_replay_file(
+    exporter: &mut dyn FileExport,
+    level: &LevelData,
+    course: &Course,
+    seed: u64,
+    tracker: &Tracker,
+) -> Result<(), anyhow::Error> {
+    let bytes = ReplayFile::encode(level, course, seed, tracker)?;
+    exporter.set_save_action(
+        Box::new(move |w| {
+            w.write_all(&bytes)?;
+            Ok(())
+        }),
+        REPLAY_FILE_NAME,
+    )
+}
+
+pub fn draw_viewer(
+    app: &mut App,
+    gfx: &mut Graphics,
+    plugins: &mut Plugins,
+    res: &Resources,
+    settings: &Settings,
+    state: &mut ReplayState,
+) -> Option<Action> {
+    for dir in Direction::iter() {
+        let action = Action::Scroll(dir);
+        if check_key_press(app, settings, action) {
+            state.process_action(action);
+        }
+    }
+    let time = app.timer.elapsed();
+    for &act in VIEWER_ACTIONS {
+        if check_key_press(app, settings, act) {
+            state.process_command(act, time);
+        }
+    }
+    let mut command: Option<Action> = None;
+    let mut draw_rect = Rect::NOTHING;
+    let output = plugins.egui(|ctx| {
+        let seek = draw_goal_panel(
+            &state.level_data,
+            Some(state.tracker.get_finishes()),
+            Some(state.tracker.get_crashes()),
+            state.player.divergence.as_ref(),
+            ctx,
+        );
+        let pps = PlaybackPanelState::Viewing(
+            state.player.playback,
+            state.player.round,
+            state.tracker.rounds_available(),
+        );
+        command = draw_playback_panel(pps, Some(&mut state.player.speed), settings, ctx).or(seek);
+        egui::Window::new("Overlays")
+            .anchor(egui::Align2::RIGHT_TOP, (-8.0, 8.0))
+            .default_open(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.checkbox(&mut state.show_breakpoints, "Breakpoints");
+            });
+        breakpoint_window(
+            ctx,
+            &mut state.player.breakpoints,
+            &mut state.breakpoint_draft,
+            &mut state.show_breakpoints,
+            state.level_data.cars,
+        );
+        draw_rect = ctx.available_rect() * ctx.zoom_factor();
+        if state.show_keys {
+            key_window(ctx, settings, false);
+        }
+        let _ = state.exporter.update(ctx);
+        file_error_window(ctx, &mut state.file_error);
+    });
+    match command {
+        Some(Action::SaveReplay) => {
+            if let Err(e) = export_replay_file(
+                state.exporter.as_mut(),
+                &state.level_data,
+                &state.course,
+                state.seed,
+                &state.tracker,
+            ) {
+                state.file_error = Some(e.to_string());
+            }
+        }
+        Some(cmd) => state.process_command(cmd, time),
+        None => (),
+    }
+    state.check_advance(time);
+    let player = &state.player;
+    let animating = settings.smooth_animation
+        && !player.animations.is_empty()
+        && time < player.round_display_time + player.round_duration();
+    let mut graphics = TileGraphics {
+        res,
+        zoom: settings.zoom.tile_size,
+        bg_color: &settings.bg_color,
+        draw: gfx.create_draw(),
+        round: if animating {
+            player.animation_round
+        } else {
+            player.round
+        },
+    };
+    let offset = get_draw_offset(&state.view_center, &draw_rect);
+    graphics.set_offset(&offset);
+    graphics.draw_course(&state.course);
+    if animating {
+        let t = (time - player.round_display_time).div_duration_f32(player.round_duration());
+        for anim in player.animations.iter() {
+            let pos = anim.position_at_time(t);
+            graphics.draw_car_smooth(anim.id, &pos);
+            graphics.draw_car_number_smooth(anim.id, &pos);
+        }
+    } else {
+        for car in state.get_cars() {
+            graphics.draw_car(car);
+            graphics.draw_car_number(car);
+        }
+    }
+    gfx.render(&graphics.draw);
+    gfx.render(&output);
+    command
+}